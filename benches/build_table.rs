@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lights_for_omen_sequencer::{build_table, get_keys, ChannelOrder};
+
+fn bench_build_table(c: &mut Criterion) {
+    let keys = get_keys();
+    let mut overrides = HashMap::new();
+    overrides.insert("all".to_string(), 0x102030);
+    for (i, key) in keys.iter().enumerate().step_by(3) {
+        overrides.insert(key.to_string(), i as u32 * 0x010101);
+    }
+
+    c.bench_function("build_table", |b| {
+        b.iter(|| build_table(black_box(&keys), black_box(&overrides), 0xffffff, ChannelOrder::Rgb))
+    });
+}
+
+criterion_group!(benches, bench_build_table);
+criterion_main!(benches);
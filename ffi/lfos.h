@@ -0,0 +1,35 @@
+/* C API for lights-for-omen-sequencer, built with:
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * Every function returns 0 when it works, or the exit code the command line gives for the same error. Calls that
+ * open the keyboard take turns, since only one of them can have it claimed at a time; error messages are kept per
+ * thread. */
+#ifndef LFOS_H
+#define LFOS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Applies a JSON object of keys and colors, like {"all": "000000", "wasd": "ff0000"}, and saves it as the current
+ * colors */
+int lfos_apply_json(const char *json);
+
+/* Builds the packets lfos_apply_json would send into out, without touching the keyboard */
+int lfos_table_json(const char *json, uint8_t *out, size_t len);
+
+/* How many bytes lfos_table_json writes */
+size_t lfos_table_len(void);
+
+/* The message for the last error on this thread, or NULL if the last call worked. It stays valid until the next
+ * call on the same thread */
+const char *lfos_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
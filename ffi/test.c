@@ -0,0 +1,43 @@
+/* Checks the C API without a keyboard:
+ *     cargo rustc --lib --features ffi --crate-type cdylib
+ *     cc ffi/test.c -Iffi -Ltarget/debug -llights_for_omen_sequencer -o target/ffi-test
+ *     LD_LIBRARY_PATH=target/debug target/ffi-test */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "lfos.h"
+
+static int failed = 0;
+
+static void check(int ok, const char *what) {
+    printf("[%s] %s\n", ok ? "ok" : "FAIL", what);
+    if (!ok) {
+        failed = 1;
+    }
+}
+
+int main(void) {
+    size_t len = lfos_table_len();
+    uint8_t *red = malloc(len);
+    uint8_t *blue = malloc(len);
+
+    check(lfos_table_json("{\"all\": \"ff0000\"}", red, len) == 0, "a valid scheme builds");
+    check(lfos_last_error() == NULL, "no error is left after a call that worked");
+    check(lfos_table_json("{\"all\": \"0000ff\"}", blue, len) == 0, "another scheme builds");
+    check(memcmp(red, blue, len) != 0, "different colors give different packets");
+
+    check(lfos_table_json("{\"all\": \"nope\"}", red, len) == 2, "a bad color gives exit code 2");
+    check(lfos_last_error() != NULL && strstr(lfos_last_error(), "nope") != NULL, "the error names the bad color");
+    check(lfos_table_json("[1, 2]", red, len) == 2, "JSON that isn't an object is turned down");
+    check(lfos_table_json(NULL, red, len) == 2, "NULL is turned down");
+    check(lfos_table_json("{}", red, len - 1) == 2, "a short buffer is turned down");
+
+    /* Without a keyboard this fails, but it has to say why */
+    int applied = lfos_apply_json("{\"all\": \"00ff00\"}");
+    check(applied == 0 || lfos_last_error() != NULL, "applying either works or explains itself");
+
+    free(red);
+    free(blue);
+    return failed;
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+};
+
+use crate::{
+    build_lfos_table,
+    effect::{saved_colors, send, FrameScheduler},
+    error::{LfosError, LfosResult},
+    Command, Keyboard, LFOS,
+};
+
+// Anything quieter than this many decibels below full scale counts as silence
+const FLOOR_DB: f32 = -50.0;
+// Seconds it takes the level to fall by two thirds once the sound stops, so beats fade out instead of flickering
+const DECAY: f32 = 0.25;
+
+fn audio_error(err: impl std::fmt::Display) -> LfosError {
+    LfosError::Io(io::Error::other(format!("could not capture audio: {}", err)))
+}
+
+// Loudness of a block of samples, from 0 for silence up to 1 for full scale
+fn loudness(samples: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = samples.fold((0.0, 0), |(sum, count), sample| (sum + sample * sample, count + 1));
+    if count == 0 {
+        return 0.0;
+    }
+    let rms = (sum / count as f32).sqrt();
+    let db = 20.0 * rms.max(f32::MIN_POSITIVE).log10();
+    ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+}
+
+fn build_stream<T>(device: &cpal::Device, config: &StreamConfig, peak: Arc<AtomicU32>) -> LfosResult<Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    device
+        .build_input_stream(
+            config,
+            // Levels are never negative, so their bits sort the same way the numbers do
+            move |data: &[T], _: &_| {
+                let level = loudness(data.iter().map(|sample| sample.to_sample::<f32>()));
+                peak.fetch_max(level.to_bits(), Ordering::Relaxed);
+            },
+            |err| eprintln!("audio: {}", err),
+            None,
+        )
+        .map_err(audio_error)
+}
+
+// Keeps the loudest level heard since it was last taken, until the stream is dropped
+fn capture(peak: Arc<AtomicU32>) -> LfosResult<Stream> {
+    let host = cpal::default_host();
+    // WASAPI records what the default output plays when asked to record from it, other hosts use the default input
+    let (device, config) = if cfg!(windows) {
+        let device = host.default_output_device().ok_or_else(|| audio_error("no output device"))?;
+        let config = device.default_output_config().map_err(audio_error)?;
+        (device, config)
+    } else {
+        let device = host.default_input_device().ok_or_else(|| audio_error("no input device"))?;
+        let config = device.default_input_config().map_err(audio_error)?;
+        (device, config)
+    };
+
+    let format = config.sample_format();
+    let config = config.config();
+    let stream = match format {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, peak),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, peak),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, peak),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, peak),
+        format => Err(audio_error(format!("{} samples are not supported", format))),
+    }?;
+    stream.play().map_err(audio_error)?;
+    Ok(stream)
+}
+
+pub fn run(lfos: &LFOS, command: &Command, keys: &[String], low: u32, high: u32) -> LfosResult<()> {
+    let peak = Arc::new(AtomicU32::new(0));
+    let _stream = capture(peak.clone())?;
+
+    let mut keyboard = if command.dry_run { None } else { Some(Keyboard::open(command)?) };
+    let running = Arc::new(AtomicBool::new(true));
+    let handler = running.clone();
+    ctrlc::set_handler(move || handler.store(false, Ordering::SeqCst)).map_err(io::Error::other)?;
+
+    // Jumps up with every beat and falls back smoothly
+    let fall = (-1.0 / (command.fps as f32 * DECAY)).exp();
+    let mut base: HashMap<String, u32> = saved_colors(lfos, command)?.into_iter().collect();
+    let mut level: f32 = 0.0;
+    let mut last = None;
+    let mut scheduler = FrameScheduler::new(command.fps);
+    while running.load(Ordering::SeqCst) {
+        level = (level * fall).max(f32::from_bits(peak.swap(0, Ordering::Relaxed)));
+        let color = command.interpolation.interpolate(low, high, level as f64);
+        for key in keys {
+            base.insert(key.clone(), color);
+        }
+        let table = build_lfos_table(lfos, &base, command);
+        if last.as_ref() != Some(&table) {
+            send(&mut keyboard, command, &table);
+            last = Some(table);
+        }
+        scheduler.wait();
+    }
+    eprintln!("{}", scheduler.summary());
+
+    let overrides = saved_colors(lfos, command)?.into_iter().collect();
+    send(&mut keyboard, command, &build_lfos_table(lfos, &overrides, command));
+    Ok(())
+}
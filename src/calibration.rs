@@ -0,0 +1,65 @@
+use crate::{
+    error::{LfosError, LfosResult, Problem},
+    expand_keys, profile_line, read_profile, LFOS,
+};
+
+// Red, green and blue multipliers for every entry of get_keys, in the same order
+pub type Calibration = Vec<[f64; 3]>;
+
+fn multipliers(value: &toml::Value) -> Option<[f64; 3]> {
+    let number = |value: &toml::Value| {
+        let number = value.as_float().or_else(|| value.as_integer().map(|n| n as f64))?;
+        (0.0..=1.0).contains(&number).then_some(number)
+    };
+    match value {
+        toml::Value::Array(channels) => match channels.as_slice() {
+            [red, green, blue] => Some([number(red)?, number(green)?, number(blue)?]),
+            _ => None,
+        },
+        value => number(value).map(|n| [n; 3]),
+    }
+}
+
+pub fn load(lfos: &LFOS, path: &str) -> LfosResult<Calibration> {
+    let name = if path == "-" { "<stdin>" } else { path };
+    let text = read_profile(path)?;
+    let table: toml::Table = text.parse().map_err(|err: toml::de::Error| LfosError::ProfileSyntax {
+        name: name.to_string(),
+        message: err.to_string(),
+    })?;
+
+    let mut calibration = vec![[1.0; 3]; lfos.keys.len()];
+    let mut problems = Vec::new();
+    for (key, value) in &table {
+        let checked = expand_keys(lfos, key).and_then(|keys| {
+            let multipliers = multipliers(value).ok_or_else(|| LfosError::InvalidValue {
+                option: key.clone(),
+                value: value.to_string(),
+                expected: "a multiplier from 0 to 1, or a list of one for red, green and blue".to_string(),
+            })?;
+            Ok((keys, multipliers))
+        });
+        match checked {
+            Ok((keys, multipliers)) => {
+                for key in keys {
+                    if let Some(index) = lfos.keys.iter().position(|name| *name == key) {
+                        calibration[index] = multipliers;
+                    }
+                }
+            }
+            Err(err) => problems.push(Problem {
+                line: profile_line(&text, key),
+                key: Some(key.clone()),
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(LfosError::InvalidProfile {
+            name: name.to_string(),
+            problems,
+        });
+    }
+    Ok(calibration)
+}
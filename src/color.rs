@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use crate::color_component;
+
+pub const NAMED_COLORS: &[(&str, u32)] = &[
+    ("black", 0x000000),
+    ("white", 0xffffff),
+    ("gray", 0x808080),
+    ("red", 0xff0000),
+    ("orange", 0xff8000),
+    ("yellow", 0xffff00),
+    ("lime", 0x80ff00),
+    ("green", 0x00ff00),
+    ("teal", 0x008080),
+    ("cyan", 0x00ffff),
+    ("blue", 0x0000ff),
+    ("navy", 0x000080),
+    ("purple", 0x8000ff),
+    ("magenta", 0xff00ff),
+    ("pink", 0xff80c0),
+];
+
+pub fn named_color(name: &str) -> Option<u32> {
+    NAMED_COLORS
+        .iter()
+        .find(|(named, _)| named.eq_ignore_ascii_case(name))
+        .map(|(_, color)| *color)
+}
+
+pub fn nearest_color(color: u32) -> &'static str {
+    let distance = |other: u32| {
+        [16, 8, 0].iter().fold(0, |sum, ofset| {
+            let d = color_component(color, *ofset) as i32 - color_component(other, *ofset) as i32;
+            sum + d * d
+        })
+    };
+    NAMED_COLORS
+        .iter()
+        .min_by_key(|(_, named)| distance(*named))
+        .map(|(name, _)| *name)
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Blend {
+    Replace,
+    Add,
+    Max,
+    Average,
+}
+
+impl Blend {
+    pub fn parse(value: &str) -> Option<Blend> {
+        match value {
+            "replace" => Some(Blend::Replace),
+            "add" => Some(Blend::Add),
+            "max" => Some(Blend::Max),
+            "average" => Some(Blend::Average),
+            _ => None,
+        }
+    }
+
+    pub fn mix(self, earlier: u32, later: u32) -> u32 {
+        if self == Blend::Replace {
+            return later;
+        }
+        [16, 8, 0].iter().fold(0, |color, ofset| {
+            let a = color_component(earlier, *ofset) as u32;
+            let b = color_component(later, *ofset) as u32;
+            let mixed = match self {
+                Blend::Add => (a + b).min(0xff),
+                Blend::Max => a.max(b),
+                _ => (a + b).div_ceil(2),
+            };
+            color | mixed << ofset
+        })
+    }
+
+    pub fn insert(self, overrides: &mut HashMap<String, u32>, key: &str, color: u32) {
+        if self == Blend::Replace {
+            overrides.insert(key.to_string(), color);
+        } else if key == "all" {
+            // Keys colored before "all" would otherwise keep their color, so blend into them too
+            for value in overrides.values_mut() {
+                *value = self.mix(*value, color);
+            }
+            overrides.entry(key.to_string()).or_insert(color);
+        } else {
+            let color = match overrides.get(key).or_else(|| overrides.get("all")) {
+                Some(earlier) => self.mix(*earlier, color),
+                None => color,
+            };
+            overrides.insert(key.to_string(), color);
+        }
+    }
+}
+
+pub fn interpolate(from: u32, to: u32, t: f64) -> u32 {
+    [16, 8, 0].iter().fold(0, |color, ofset| {
+        let a = color_component(from, *ofset) as f64;
+        let b = color_component(to, *ofset) as f64;
+        color | ((a + (b - a) * t).round() as u32) << ofset
+    })
+}
+
+// Where fades and gradients mix their colors. Halfway between two colors in Oklab looks halfway to the eye, while
+// plain RGB goes through darker and duller colors, like the gray between red and cyan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    Rgb,
+    Oklab,
+}
+
+impl ColorSpace {
+    pub fn parse(value: &str) -> Option<ColorSpace> {
+        match value {
+            "rgb" => Some(ColorSpace::Rgb),
+            "oklab" => Some(ColorSpace::Oklab),
+            _ => None,
+        }
+    }
+
+    pub fn interpolate(self, from: u32, to: u32, t: f64) -> u32 {
+        match self {
+            ColorSpace::Rgb => interpolate(from, to, t),
+            ColorSpace::Oklab => {
+                let (a, b) = (rgb_to_oklab(from), rgb_to_oklab(to));
+                oklab_to_rgb([0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t))
+            }
+        }
+    }
+}
+
+pub fn gradient(stops: &[u32], t: f64, space: ColorSpace) -> u32 {
+    let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+    let i = (position.floor() as usize).min(stops.len() - 2);
+    space.interpolate(stops[i], stops[i + 1], position - i as f64)
+}
+
+fn to_linear(channel: u8) -> f64 {
+    let c = channel as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(c: f64) -> u32 {
+    let c = if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c.clamp(0.0, 1.0) * 255.0).round() as u32
+}
+
+// L, a and b as defined by Björn Ottosson, from sRGB with the usual gamma
+pub fn rgb_to_oklab(color: u32) -> [f64; 3] {
+    let [r, g, b] = [16, 8, 0].map(|ofset| to_linear(color_component(color, ofset)));
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+// Colors outside of what sRGB can show are clipped one channel at a time
+pub fn oklab_to_rgb([lightness, a, b]: [f64; 3]) -> u32 {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    let r = 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s;
+    let g = -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s;
+    let b = -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s;
+    from_linear(r) << 16 | from_linear(g) << 8 | from_linear(b)
+}
+
+pub fn rgb_to_hsv(color: u32) -> (f64, f64, f64) {
+    let r = color_component(color, 16) as f64 / 255.0;
+    let g = color_component(color, 8) as f64 / 255.0;
+    let b = color_component(color, 0) as f64 / 255.0;
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    (hue, saturation, max)
+}
+
+pub fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> u32 {
+    let hue = hue.rem_euclid(360.0);
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    let channel = |c: f64| ((c + m) * 255.0).round() as u32;
+
+    channel(r) << 16 | channel(g) << 8 | channel(b)
+}
+
+pub fn hue_shift(color: u32, degrees: f64) -> u32 {
+    let (hue, saturation, value) = rgb_to_hsv(color);
+    hsv_to_rgb(hue + degrees, saturation, value)
+}
+
+// Relative luminance as defined by WCAG, from 0 for black to 1 for white
+fn luminance(color: u32) -> f64 {
+    let [r, g, b] = [16, 8, 0].map(|ofset| to_linear(color_component(color, ofset)));
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+pub fn contrast_text_color(background: u32) -> u32 {
+    // Above this, black text has more contrast with the background than white text does
+    if luminance(background) > 0.179 {
+        0x000000
+    } else {
+        0xffffff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hue_shift_by_a_full_turn_changes_nothing() {
+        for color in [0xff0000, 0x80ff00, 0x123456] {
+            assert_eq!(hue_shift(color, 360.0), hue_shift(color, 0.0));
+            assert_eq!(hue_shift(color, 0.0), color);
+        }
+    }
+
+    #[test]
+    fn hue_shift_by_half_a_turn_gives_the_opposite_color() {
+        assert_eq!(hue_shift(0xff0000, 180.0), 0x00ffff);
+        assert_eq!(hue_shift(0x00ff00, 180.0), 0xff00ff);
+        assert_eq!(hue_shift(0x0000ff, -180.0), 0xffff00);
+    }
+
+    #[test]
+    fn blend_mixes_each_channel_on_its_own() {
+        let (earlier, later) = (0x804020, 0x40c0ff);
+        assert_eq!(Blend::Replace.mix(earlier, later), 0x40c0ff);
+        assert_eq!(Blend::Add.mix(earlier, later), 0xc0ffff);
+        assert_eq!(Blend::Max.mix(earlier, later), 0x80c0ff);
+        assert_eq!(Blend::Average.mix(earlier, later), 0x608090);
+    }
+
+    #[test]
+    fn blend_mixes_a_key_with_all_when_it_has_no_color_yet() {
+        let mut overrides = HashMap::from([("all".to_string(), 0x800000)]);
+        Blend::Add.insert(&mut overrides, "home", 0x000080);
+        Blend::Add.insert(&mut overrides, "all", 0x008000);
+        assert_eq!(overrides, HashMap::from([("all".to_string(), 0x808000), ("home".to_string(), 0x808080)]));
+    }
+
+    #[test]
+    fn contrast_text_switches_to_black_between_two_grays() {
+        assert_eq!(contrast_text_color(0x757575), 0xffffff);
+        assert_eq!(contrast_text_color(0x767676), 0x000000);
+        assert_eq!(contrast_text_color(0x000000), 0xffffff);
+        assert_eq!(contrast_text_color(0xffffff), 0x000000);
+    }
+
+    #[test]
+    fn oklab_matches_the_reference_values() {
+        // Reference values from Björn Ottosson's Oklab post
+        let oklab = [
+            (0xffffff, [1.0, 0.0, 0.0]),
+            (0x000000, [0.0, 0.0, 0.0]),
+            (0xff0000, [0.627955, 0.224863, 0.125846]),
+            (0x00ff00, [0.866440, -0.233888, 0.179498]),
+            (0x0000ff, [0.452014, -0.032457, -0.311528]),
+        ];
+        for (rgb, expected) in oklab {
+            let lab = rgb_to_oklab(rgb);
+            assert!(lab.iter().zip(expected).all(|(got, expected)| (got - expected).abs() <= 1e-4), "{:06x} gives {:?}", rgb, lab);
+        }
+    }
+
+    #[test]
+    fn colors_come_back_from_oklab_unchanged() {
+        for rgb in NAMED_COLORS.iter().map(|(_, rgb)| *rgb).chain((0..=255).map(|gray| gray * 0x010101)) {
+            assert_eq!(oklab_to_rgb(rgb_to_oklab(rgb)), rgb, "{:06x}", rgb);
+        }
+        let (from, to) = (0xff0000, 0x00ffff);
+        assert_eq!([0.0, 1.0].map(|t| ColorSpace::Oklab.interpolate(from, to, t)), [from, to]);
+    }
+}
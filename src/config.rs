@@ -0,0 +1,161 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{LfosError, LfosResult};
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+}
+
+// Defaults for options that people want the same on every call, the command line still wins over them
+#[derive(Default)]
+pub struct Config {
+    pub brightness: Option<u32>,
+    pub calibration: Option<String>,
+    pub default_command: Option<Vec<String>>,
+    // Colors for keys and groups that nothing else set, in the order they were written
+    pub colors: Vec<(String, String)>,
+}
+
+fn config_error(path: &Path, reason: impl ToString) -> LfosError {
+    LfosError::Config {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+// Splits a command the way a shell would for simple cases: on spaces, keeping quoted parts together
+fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => arg.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return None;
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Some(args)
+}
+
+impl Config {
+    pub fn load() -> LfosResult<Config> {
+        match config_path() {
+            Some(path) => Config::read(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    // A missing file is the same as an empty one
+    fn read(path: &Path) -> LfosResult<Config> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(path, &text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(config_error(path, err)),
+        }
+    }
+
+    fn parse(path: &Path, text: &str) -> LfosResult<Config> {
+        let table: toml::Table = text.parse().map_err(|err| config_error(path, err))?;
+
+        let brightness = match table.get("brightness") {
+            Some(value) => Some(
+                value
+                    .as_integer()
+                    .filter(|brightness| (0..=100).contains(brightness))
+                    .ok_or_else(|| config_error(path, "brightness must be a percentage from 0 to 100"))?
+                    as u32,
+            ),
+            None => None,
+        };
+
+        let calibration = match table.get("calibration") {
+            Some(value) => Some(
+                value
+                    .as_str()
+                    .ok_or_else(|| config_error(path, "calibration must be the path of a calibration file"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        let default_command = match table.get("default_command") {
+            Some(value) => {
+                let args = value
+                    .as_str()
+                    .and_then(split_command)
+                    .filter(|args| !args.is_empty())
+                    .ok_or_else(|| config_error(path, "default_command must be a command like \"effect cycle\", with matching quotes"))?;
+                Some(args)
+            }
+            None => None,
+        };
+
+        let colors = match table.get("colors") {
+            Some(toml::Value::Table(colors)) => colors
+                .iter()
+                .map(|(key, color)| match color.as_str() {
+                    Some(color) => Ok((key.clone(), color.to_string())),
+                    None => Err(config_error(path, format!("the color for '{}' in [colors] must be a string like \"ff0000\"", key))),
+                })
+                .collect::<LfosResult<_>>()?,
+            Some(_) => return Err(config_error(path, "colors must be a table of keys or groups and their colors")),
+            None => Vec::new(),
+        };
+
+        Ok(Config {
+            brightness,
+            calibration,
+            default_command,
+            colors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_missing_file_gives_the_defaults() {
+        let path = std::env::temp_dir().join(format!("{}-{}-missing.toml", env!("CARGO_PKG_NAME"), std::process::id()));
+        let config = Config::read(&path).unwrap();
+        assert_eq!(config.brightness, None);
+        assert_eq!(config.calibration, None);
+        assert_eq!(config.default_command, None);
+        assert!(config.colors.is_empty());
+    }
+
+    #[test]
+    fn the_file_is_read_into_the_config() {
+        let text = "brightness = 60\ndefault_command = \"effect cycle\"\n[colors]\nfkeys = \"red\"\nf1 = \"blue\"\n";
+        let config = Config::parse(Path::new("config.toml"), text).unwrap();
+        assert_eq!(config.brightness, Some(60));
+        assert_eq!(config.default_command, Some(vec!["effect".to_string(), "cycle".to_string()]));
+        assert_eq!(config.colors, [("fkeys".to_string(), "red".to_string()), ("f1".to_string(), "blue".to_string())]);
+        assert!(Config::parse(Path::new("config.toml"), "brightness = 101").is_err());
+    }
+}
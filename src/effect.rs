@@ -0,0 +1,359 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    build_lfos_table,
+    color::{hsv_to_rgb, interpolate, ColorSpace},
+    error::LfosResult,
+    load_state, print_table, resolve_colors, save_state,
+    state::State,
+    Command, Keyboard, LFOS,
+};
+
+// How many rows behind the head of a drop it takes to fade out
+const MATRIX_TAIL: f64 = 3.0;
+
+#[derive(Clone, Debug)]
+pub enum Effect {
+    // Only the given keys go around the color wheel when there are any, the rest keep their saved colors
+    Cycle { speed: f64, offset: f64, keys: Option<Vec<String>> },
+    Matrix { color: u32, speed: f64, density: f64 },
+    PaletteBreathe { colors: Vec<u32>, hold: Duration, fade: Duration, space: ColorSpace },
+    Wander { colors: Vec<u32>, speed: f64, seed: u64, space: ColorSpace },
+    // Steady colors from a profile, with some keys switching between two colors
+    Blink { base: HashMap<String, u32>, blinks: Vec<Blink> },
+}
+
+#[derive(Clone, Debug)]
+pub struct Blink {
+    pub keys: Vec<String>,
+    pub on: u32,
+    pub off: u32,
+    pub period: Duration,
+}
+
+// Cheap and stable noise, so every column can have its own drops without keeping any state between frames
+fn noise(column: u8, n: u64) -> f64 {
+    let mut x = (column as u64) << 32 ^ n;
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (x ^ (x >> 31)) as f64 / u64::MAX as f64
+}
+
+fn matrix_brightness(column: u8, row: u8, rows: f64, seconds: f64, speed: f64, density: f64) -> f64 {
+    // Each pass of a drop goes past the bottom row until its tail is gone too
+    let period = rows + MATRIX_TAIL;
+    let position = seconds * speed * (0.6 + 0.8 * noise(column, 0)) + period * noise(column, 1);
+    let pass = (position / period).floor();
+    if noise(column, pass as u64 + 2) >= density {
+        return 0.0;
+    }
+
+    let behind = position - pass * period - row as f64;
+    if (0.0..=MATRIX_TAIL).contains(&behind) {
+        1.0 - behind / (MATRIX_TAIL + 1.0)
+    } else {
+        0.0
+    }
+}
+
+// Where effects get the time from. A frame only depends on the time it's asked for, so new effects should take it
+// from here instead of Instant::now, and any frame can then be checked by asking for it with a FixedClock
+pub trait Clock {
+    fn elapsed(&self) -> Duration;
+}
+
+// Every key drifts from one color of the palette to another at its own pace, picked from the seed, so the same seed
+// always gives the same glow
+fn wander_color(index: u8, seconds: f64, colors: &[u32], speed: f64, seed: u64, space: ColorSpace) -> u32 {
+    let noise = |n: u64| noise(index, seed.wrapping_mul(0x100000001b3).wrapping_add(n));
+    let rate = speed * (0.5 + noise(0));
+    let position = seconds * rate + noise(1) * colors.len() as f64;
+    let step = position.floor() as u64;
+    let pick = |step: u64| colors[((noise(step + 2) * colors.len() as f64) as usize).min(colors.len() - 1)];
+    // Eases in and out, so keys linger on each color instead of moving at an even pace
+    let t = position.fract();
+    space.interpolate(pick(step), pick(step + 1), t * t * (3.0 - 2.0 * t))
+}
+
+impl Effect {
+    pub fn frame(&self, lfos: &LFOS, clock: &impl Clock) -> HashMap<String, u32> {
+        let seconds = clock.elapsed().as_secs_f64();
+        match self {
+            Effect::Cycle { speed, offset, keys } => lfos
+                .positions
+                .iter()
+                .filter(|(key, _)| keys.as_ref().is_none_or(|keys| keys.iter().any(|k| k == *key)))
+                .map(|(key, (_, col))| {
+                    let hue = seconds * speed * 360.0 + *col as f64 * offset;
+                    (key.to_string(), hsv_to_rgb(hue, 1.0, 1.0))
+                })
+                .collect(),
+            Effect::Matrix { color, speed, density } => {
+                let rows = lfos.positions.values().map(|(row, _)| *row).max().unwrap_or(0) as f64 + 1.0;
+                lfos.positions
+                    .iter()
+                    .map(|(key, (row, col))| {
+                        let brightness = matrix_brightness(*col, *row, rows, seconds, *speed, *density);
+                        (key.to_string(), interpolate(0, *color, brightness))
+                    })
+                    .collect()
+            }
+            Effect::PaletteBreathe { colors, hold, fade, space } => {
+                // Each color is held, then crossfades into the next one, and the last one back into the first
+                let step = (*hold + *fade).as_secs_f64();
+                let position = seconds % (step * colors.len() as f64);
+                let index = ((position / step) as usize).min(colors.len() - 1);
+                let faded = (position - index as f64 * step - hold.as_secs_f64()).max(0.0);
+                let next = colors[(index + 1) % colors.len()];
+                let color = match fade.is_zero() {
+                    true => colors[index],
+                    false => space.interpolate(colors[index], next, faded / fade.as_secs_f64()),
+                };
+                HashMap::from([("all".to_string(), color)])
+            }
+            Effect::Wander { colors, speed, seed, space } => lfos
+                .keys
+                .iter()
+                .enumerate()
+                .filter(|(_, key)| **key != "????")
+                .map(|(index, key)| (key.to_string(), wander_color(index as u8, seconds, colors, *speed, *seed, *space)))
+                .collect(),
+            Effect::Blink { base, blinks } => {
+                let mut overrides = base.clone();
+                for blink in blinks {
+                    // On for the first half of every period and off for the second
+                    let on = (seconds / blink.period.as_secs_f64()).fract() < 0.5;
+                    for key in &blink.keys {
+                        overrides.insert(key.clone(), if on { blink.on } else { blink.off });
+                    }
+                }
+                overrides
+            }
+        }
+    }
+}
+
+// Sleeps are cut into steps this long, so Ctrl-C doesn't have to wait out a long pause
+const SLEEP_STEP: Duration = Duration::from_millis(100);
+
+// Sleeps for the delay, or until Ctrl-C clears running, whichever comes first
+pub fn wait(running: &AtomicBool, delay: Duration) {
+    let mut left = delay;
+    while !left.is_zero() && running.load(Ordering::SeqCst) {
+        let step = left.min(SLEEP_STEP);
+        thread::sleep(step);
+        left -= step;
+    }
+}
+
+// Keeps every effect on the same frame rate, and keeps count of the frames that came too late
+pub struct FrameScheduler {
+    frame: Duration,
+    start: Instant,
+    next: Instant,
+    frames: u64,
+    dropped: u64,
+}
+
+impl FrameScheduler {
+    pub fn new(fps: u32) -> FrameScheduler {
+        let frame = Duration::from_secs(1) / fps;
+        let start = Instant::now();
+        FrameScheduler {
+            frame,
+            start,
+            next: start + frame,
+            frames: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn wait(&mut self) {
+        self.frames += 1;
+        let now = Instant::now();
+        if now <= self.next {
+            thread::sleep(self.next - now);
+            self.next += self.frame;
+            return;
+        }
+
+        // Skip the frames there was no time for, instead of rushing to catch up
+        let behind = ((now - self.next).as_nanos() / self.frame.as_nanos()) as u32 + 1;
+        if self.dropped == 0 {
+            eprintln!("effect: can't keep up, frames are being dropped");
+        }
+        self.dropped += behind as u64;
+        let start = self.next + self.frame * behind;
+        thread::sleep(start - now);
+        self.next = start + self.frame;
+    }
+
+    pub fn summary(&self) -> String {
+        let seconds = self.elapsed().as_secs_f64();
+        format!(
+            "effect: {} frames in {:.1}s, {:.1} fps on average, {} dropped",
+            self.frames,
+            seconds,
+            self.frames as f64 / seconds.max(f64::EPSILON),
+            self.dropped
+        )
+    }
+}
+
+impl Clock for FrameScheduler {
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+pub fn send(keyboard: &mut Option<Keyboard>, command: &Command, table: &[Vec<u8>]) {
+    match keyboard {
+        Some(keyboard) => keyboard.write_table(command, table),
+        None => print_table(command, table),
+    }
+}
+
+// The colors to go back to once an effect is over, plain white when nothing was saved
+pub fn saved_colors(lfos: &LFOS, command: &Command) -> LfosResult<BTreeMap<String, u32>> {
+    Ok(match load_state(command)? {
+        Some(state) => state.colors,
+        None => resolve_colors(lfos, &HashMap::new()),
+    })
+}
+
+// Fades keys from their saved colors up to one color and back down once, then leaves everything as it was
+pub fn pulse(lfos: &LFOS, command: &Command, keys: &[String], color: u32, duration: Duration) -> LfosResult<()> {
+    let base = saved_colors(lfos, command)?;
+    let mut keyboard = if command.dry_run { None } else { Some(Keyboard::open(command)?) };
+
+    let frames = ((duration.as_secs_f64() * command.fps as f64).round() as u32).max(2);
+    let mut scheduler = FrameScheduler::new(command.fps);
+    for frame in 0..=frames {
+        // Goes up for the first half and back down for the second, ending on the saved colors
+        let level = 1.0 - (2.0 * frame as f64 / frames as f64 - 1.0).abs();
+        let mut overrides: HashMap<String, u32> = base.clone().into_iter().collect();
+        for key in keys {
+            let from = base.get(key).copied().unwrap_or(0xffffff);
+            overrides.insert(key.clone(), command.interpolation.interpolate(from, color, level));
+        }
+        send(&mut keyboard, command, &build_lfos_table(lfos, &overrides, command));
+        if frame < frames {
+            scheduler.wait();
+        }
+    }
+    Ok(())
+}
+
+pub fn run(lfos: &LFOS, command: &Command, effect: &Effect) -> LfosResult<()> {
+    let mut keyboard = if command.dry_run { None } else { Some(Keyboard::open(command)?) };
+    let running = Arc::new(AtomicBool::new(true));
+    let handler = running.clone();
+    ctrlc::set_handler(move || handler.store(false, Ordering::SeqCst)).map_err(std::io::Error::other)?;
+
+    // A cycle over some of the keys is laid over the saved colors every frame
+    let base: HashMap<String, u32> = match effect {
+        Effect::Cycle { keys: Some(_), .. } => saved_colors(lfos, command)?.into_iter().collect(),
+        _ => HashMap::new(),
+    };
+    let mut scheduler = FrameScheduler::new(command.fps);
+    let mut last = None;
+    while running.load(Ordering::SeqCst) {
+        let mut overrides = base.clone();
+        overrides.extend(effect.frame(lfos, &scheduler));
+        let table = build_lfos_table(lfos, &overrides, command);
+        // Blinking keys change a couple of times per second at most, the frames in between aren't sent again
+        if last.as_ref() != Some(&table) {
+            send(&mut keyboard, command, &table);
+            last = Some(table);
+        }
+        scheduler.wait();
+    }
+    eprintln!("{}", scheduler.summary());
+
+    // Go back to the saved colors, or plain white without any, so the keyboard isn't left on a random frame.
+    // A blinking profile stops on its steady colors instead, and they are saved like any other profile
+    let colors = match effect {
+        Effect::Blink { base, .. } => resolve_colors(lfos, base),
+        _ => saved_colors(lfos, command)?,
+    };
+    let overrides = colors.clone().into_iter().collect();
+    send(&mut keyboard, command, &build_lfos_table(lfos, &overrides, command));
+    if let (Effect::Blink { .. }, Some(keyboard)) = (effect, &keyboard) {
+        let state = State {
+            colors,
+            ..State::default()
+        };
+        save_state(command, &state, Some(keyboard));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_lfos;
+
+    // Stopped at one moment, for checking what an effect shows at a given time
+    struct FixedClock(Duration);
+
+    impl Clock for FixedClock {
+        fn elapsed(&self) -> Duration {
+            self.0
+        }
+    }
+
+    // Frames only depend on the time they're asked for, so these are the exact colors at those moments
+    fn color_at(effect: &Effect, ms: u64, key: &str) -> Option<u32> {
+        effect.frame(&get_lfos(), &FixedClock(Duration::from_millis(ms))).get(key).copied()
+    }
+
+    #[test]
+    fn breathe_holds_each_color_then_fades_to_the_next() {
+        let breathe = Effect::PaletteBreathe {
+            colors: vec![0xff0000, 0x0000ff],
+            hold: Duration::from_millis(1000),
+            fade: Duration::from_millis(1000),
+            space: ColorSpace::Rgb,
+        };
+        assert_eq!(color_at(&breathe, 0, "all"), Some(0xff0000));
+        assert_eq!(color_at(&breathe, 999, "all"), Some(0xff0000));
+        assert_eq!(color_at(&breathe, 1500, "all"), Some(0x800080));
+        assert_eq!(color_at(&breathe, 2000, "all"), Some(0x0000ff));
+    }
+
+    #[test]
+    fn cycle_turns_only_its_keys_around_the_color_wheel() {
+        let cycle = Effect::Cycle {
+            speed: 0.25,
+            offset: 0.0,
+            keys: Some(vec!["esc".to_string()]),
+        };
+        assert_eq!(color_at(&cycle, 0, "esc"), Some(0xff0000));
+        assert_eq!(color_at(&cycle, 1000, "esc"), Some(0x80ff00));
+        assert_eq!(cycle.frame(&get_lfos(), &FixedClock(Duration::ZERO)).len(), 1);
+    }
+
+    #[test]
+    fn wander_gives_the_same_frames_for_the_same_seed_only() {
+        let wander = |seed| Effect::Wander {
+            colors: vec![0xff0000, 0x0000ff],
+            speed: 0.5,
+            seed,
+            space: ColorSpace::Rgb,
+        };
+        let (lfos, clock) = (get_lfos(), FixedClock(Duration::from_millis(12345)));
+        let frame = wander(7).frame(&lfos, &clock);
+        assert_eq!(frame, wander(7).frame(&lfos, &clock));
+        assert_ne!(frame, wander(8).frame(&lfos, &clock));
+        assert!(frame.values().all(|color| color & 0x00ff00 == 0), "wander goes outside the colors it was given");
+    }
+}
@@ -0,0 +1,338 @@
+use std::{fmt, io, num::ParseIntError};
+
+use serde_json::{json, Value};
+
+// The fix for the most common reason the keyboard can be seen but not opened
+#[cfg(target_os = "linux")]
+pub const PERMISSION_HINT: &str = "add a udev rule such as SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"03f0\", ATTRS{idProduct}==\"1f41\", MODE=\"0666\" to /etc/udev/rules.d/99-omen-sequencer.rules, then replug the keyboard";
+#[cfg(not(target_os = "linux"))]
+pub const PERMISSION_HINT: &str = "run it as an administrator, or close programs like Omen Gaming Hub that may hold the keyboard";
+
+pub type LfosResult<T> = std::result::Result<T, LfosError>;
+
+#[derive(Debug)]
+pub struct Problem {
+    pub line: Option<usize>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn to_json(&self) -> Value {
+        json!({ "line": self.line, "key": self.key, "message": self.message })
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LfosError {
+    InvalidColor { color: String, reason: String },
+    InvalidNumber(ParseIntError),
+    UnknownKey { key: String, suggestion: Option<String> },
+    UnknownOption(String),
+    MissingValue { option: String, expected: String },
+    InvalidValue { option: String, value: String, expected: String },
+    OddArgs { key: String, position: usize, fixed: String },
+    ProfileRead { path: String, source: io::Error },
+    ProfileSyntax { name: String, message: String },
+    InProfile { name: String, source: Box<LfosError> },
+    InvalidProfile { name: String, problems: Vec<Problem> },
+    InvalidShare { reason: String },
+    DefaultProfile { variable: String, source: Box<LfosError> },
+    Io(io::Error),
+    Usb(rusb::Error),
+    DeviceNotFound,
+    DeviceBusy { retries: u32 },
+    AccessDenied,
+    NoEndpoint { transfer: String },
+    SelfTest(Vec<String>),
+    GoldenMismatch { path: String, differences: Vec<String> },
+    Doctor { failed: usize },
+    State { path: String, reason: String },
+    Config { path: String, reason: String },
+    NoState,
+    PersistDisabled,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    HelperOptions { pipe: String, options: Vec<String> },
+    #[cfg_attr(all(feature = "tui", feature = "screen", feature = "audio", feature = "mqtt"), allow(dead_code))]
+    FeatureDisabled { command: String, feature: String },
+    #[cfg_attr(unix, allow(dead_code))]
+    Unsupported(String),
+}
+
+impl LfosError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LfosError::InProfile { source, .. } | LfosError::DefaultProfile { source, .. } => {
+                source.exit_code()
+            }
+            LfosError::Io(_)
+            | LfosError::ProfileRead { .. }
+            | LfosError::State { .. }
+            | LfosError::Config { .. }
+            | LfosError::NoState => 1,
+            LfosError::DeviceNotFound => 3,
+            LfosError::Usb(_) | LfosError::DeviceBusy { .. } | LfosError::AccessDenied | LfosError::NoEndpoint { .. } => 4,
+            LfosError::SelfTest(_) | LfosError::Doctor { .. } | LfosError::GoldenMismatch { .. } => 5,
+            _ => 2,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            LfosError::InvalidColor { .. } => "invalid_color",
+            LfosError::InvalidNumber(_) => "invalid_number",
+            LfosError::UnknownKey { .. } => "unknown_key",
+            LfosError::UnknownOption(_) => "unknown_option",
+            LfosError::MissingValue { .. } => "missing_value",
+            LfosError::InvalidValue { .. } => "invalid_value",
+            LfosError::OddArgs { .. } => "odd_args",
+            LfosError::ProfileRead { .. } => "profile_read",
+            LfosError::ProfileSyntax { .. } => "profile_syntax",
+            LfosError::InProfile { source, .. } | LfosError::DefaultProfile { source, .. } => {
+                source.code()
+            }
+            LfosError::InvalidProfile { .. } => "invalid_profile",
+            LfosError::InvalidShare { .. } => "invalid_share",
+            LfosError::Io(_) => "io",
+            LfosError::Usb(_) => "usb",
+            LfosError::DeviceNotFound => "device_not_found",
+            LfosError::DeviceBusy { .. } => "device_busy",
+            LfosError::AccessDenied => "access_denied",
+            LfosError::NoEndpoint { .. } => "no_endpoint",
+            LfosError::SelfTest(_) => "selftest_failed",
+            LfosError::GoldenMismatch { .. } => "golden_mismatch",
+            LfosError::Doctor { .. } => "doctor_failed",
+            LfosError::State { .. } => "state",
+            LfosError::Config { .. } => "config",
+            LfosError::NoState => "no_state",
+            LfosError::PersistDisabled => "persist_disabled",
+            LfosError::HelperOptions { .. } => "helper_options",
+            LfosError::FeatureDisabled { .. } => "feature_disabled",
+            LfosError::Unsupported(_) => "unsupported",
+        }
+    }
+
+    pub fn context(&self) -> Value {
+        match self {
+            LfosError::InvalidColor { color, reason } => json!({ "color": color, "reason": reason }),
+            LfosError::InvalidNumber(err) => json!({ "reason": err.to_string() }),
+            LfosError::UnknownKey { key, suggestion } => json!({ "key": key, "suggestion": suggestion }),
+            LfosError::UnknownOption(option) => json!({ "option": option }),
+            LfosError::MissingValue { option, expected } => {
+                json!({ "option": option, "expected": expected })
+            }
+            LfosError::InvalidValue { option, value, expected } => {
+                json!({ "option": option, "value": value, "expected": expected })
+            }
+            LfosError::ProfileRead { path, source } => {
+                json!({ "profile": path, "reason": source.to_string() })
+            }
+            LfosError::ProfileSyntax { name, message } => json!({ "profile": name, "reason": message }),
+            LfosError::InProfile { name, source } => {
+                let mut context = source.context();
+                context["profile"] = json!(name);
+                context
+            }
+            LfosError::DefaultProfile { variable, source } => {
+                let mut context = source.context();
+                context["variable"] = json!(variable);
+                context
+            }
+            LfosError::InvalidProfile { name, problems } => {
+                let problems: Vec<Value> = problems.iter().map(Problem::to_json).collect();
+                json!({ "profile": name, "problems": problems })
+            }
+            LfosError::InvalidShare { reason } => json!({ "reason": reason }),
+            LfosError::Io(err) => json!({ "reason": err.to_string() }),
+            LfosError::Usb(err) => json!({ "reason": err.to_string() }),
+            LfosError::DeviceBusy { retries } => json!({ "retries": retries }),
+            LfosError::AccessDenied => json!({ "hint": PERMISSION_HINT }),
+            LfosError::NoEndpoint { transfer } => json!({ "transfer": transfer }),
+            LfosError::SelfTest(problems) => json!({ "problems": problems }),
+            LfosError::GoldenMismatch { path, differences } => json!({ "path": path, "differences": differences }),
+            LfosError::Doctor { failed } => json!({ "failed": failed }),
+            LfosError::State { path, reason } | LfosError::Config { path, reason } => {
+                json!({ "path": path, "reason": reason })
+            }
+            LfosError::HelperOptions { pipe, options } => json!({ "pipe": pipe, "options": options }),
+            LfosError::FeatureDisabled { command, feature } => {
+                json!({ "command": command, "feature": feature })
+            }
+            LfosError::Unsupported(command) => json!({ "command": command }),
+            LfosError::OddArgs { key, position, fixed } => {
+                json!({ "key": key, "position": position, "fixed": fixed })
+            }
+            LfosError::DeviceNotFound | LfosError::NoState | LfosError::PersistDisabled => json!({}),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "code": self.code(),
+            "message": self.to_string(),
+            "exit_code": self.exit_code(),
+            "context": self.context(),
+        })
+    }
+}
+
+impl fmt::Display for LfosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LfosError::InvalidColor { color, reason } => {
+                write!(f, "Invalid color '{}': {}", color, reason)
+            }
+            LfosError::InvalidNumber(err) => write!(f, "Invalid number: {}", err),
+            LfosError::UnknownKey { key, suggestion: Some(suggestion) } => {
+                write!(f, "Unknown key or group '{}', did you mean '{}'?", key, suggestion)
+            }
+            LfosError::UnknownKey { key, suggestion: None } => {
+                write!(f, "Unknown key or group '{}', see --help for the full list", key)
+            }
+            LfosError::UnknownOption(option) => {
+                write!(f, "Unknown option '{}', see --help for the full list", option)
+            }
+            LfosError::MissingValue { option, expected } => {
+                write!(f, "{} expects {}", option, expected)
+            }
+            LfosError::InvalidValue { option, value, expected } => {
+                write!(f, "Invalid value '{}' for {}, expected {}", value, option, expected)
+            }
+            LfosError::OddArgs { key, position, fixed } => write!(
+                f,
+                "Key '{}' at position {} has no color, each key/group must be given one, like so:\n\t{}",
+                key, position, fixed
+            ),
+            LfosError::ProfileRead { path, source } => {
+                write!(f, "Could not read profile '{}': {}", path, source)
+            }
+            LfosError::ProfileSyntax { name, message } => {
+                write!(f, "Invalid profile '{}': {}", name, message)
+            }
+            LfosError::InProfile { name, source } => {
+                write!(f, "Invalid profile '{}': {}", name, source)
+            }
+            LfosError::DefaultProfile { variable, source } => {
+                write!(f, "{} (set by {})", source, variable)
+            }
+            LfosError::InvalidProfile { name, problems } => {
+                write!(f, "Profile '{}' has {} problem(s):", name, problems.len())?;
+                for problem in problems {
+                    write!(f, "\n\t{}", problem)?;
+                }
+                Ok(())
+            }
+            LfosError::InvalidShare { reason } => write!(f, "Could not use the shared colors: {}", reason),
+            LfosError::Io(err) => write!(f, "{}", err),
+            LfosError::Usb(err) => write!(f, "USB error: {}", err),
+            LfosError::DeviceNotFound => write!(f, "Could not find an Omen Sequencer keyboard"),
+            LfosError::DeviceBusy { retries: 0 } => write!(
+                f,
+                "The keyboard was found but is busy, another program may be using it; try --retry-open"
+            ),
+            LfosError::DeviceBusy { retries } => write!(
+                f,
+                "The keyboard was found but was still busy after {} retries, another program may be using it",
+                retries
+            ),
+            LfosError::AccessDenied => write!(
+                f,
+                "The keyboard was found but this user isn't allowed to use it; {}",
+                PERMISSION_HINT
+            ),
+            LfosError::NoEndpoint { transfer } => {
+                let other = if transfer == "bulk" { "interrupt" } else { "bulk" };
+                write!(
+                    f,
+                    "no writable {} endpoint found on the keyboard; try --transfer {} or --force-detach",
+                    transfer, other
+                )
+            }
+            LfosError::State { path, reason } => {
+                write!(f, "Could not use the state file '{}': {}", path, reason)
+            }
+            LfosError::Config { path, reason } => {
+                write!(f, "Could not use the config file '{}': {}", path, reason)
+            }
+            LfosError::NoState => write!(
+                f,
+                "There are no saved colors yet, apply some colors first"
+            ),
+            LfosError::PersistDisabled => write!(
+                f,
+                "This builds on the saved colors, which --no-persist turns off; run it without --no-persist"
+            ),
+            LfosError::HelperOptions { pipe, options } => write!(
+                f,
+                "A helper on '{}' applies the colors with its own options, so {} can't be used; stop it, or run without them",
+                pipe,
+                options.join(", ")
+            ),
+            LfosError::FeatureDisabled { command, feature } => write!(
+                f,
+                "'{}' is not available in this build, rebuild it with: cargo build --release --features {}",
+                command, feature
+            ),
+            LfosError::Unsupported(command) => {
+                write!(f, "'{}' is not supported on this platform", command)
+            }
+            LfosError::Doctor { failed } => write!(f, "doctor: {} check(s) failed", failed),
+            LfosError::SelfTest(problems) => {
+                write!(f, "selftest found {} problem(s):", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n\t{}", problem)?;
+                }
+                Ok(())
+            }
+            LfosError::GoldenMismatch { path, differences } => {
+                write!(f, "The table doesn't match '{}', {} byte(s) differ:", path, differences.len())?;
+                for difference in differences {
+                    write!(f, "\n\t{}", difference)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LfosError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LfosError::InvalidNumber(err) => Some(err),
+            LfosError::ProfileRead { source, .. } => Some(source),
+            LfosError::InProfile { source, .. } | LfosError::DefaultProfile { source, .. } => {
+                Some(source.as_ref())
+            }
+            LfosError::Io(err) => Some(err),
+            LfosError::Usb(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusb::Error> for LfosError {
+    fn from(err: rusb::Error) -> Self {
+        LfosError::Usb(err)
+    }
+}
+
+impl From<ParseIntError> for LfosError {
+    fn from(err: ParseIntError) -> Self {
+        LfosError::InvalidNumber(err)
+    }
+}
+
+impl From<io::Error> for LfosError {
+    fn from(err: io::Error) -> Self {
+        LfosError::Io(err)
+    }
+}
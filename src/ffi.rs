@@ -0,0 +1,127 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{c_char, c_int, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    sync::Mutex,
+};
+
+use crate::{
+    apply_json_text, build_lfos_table, config::Config, error::LfosError, error::LfosResult, get_lfos, resolve_colors, save_state,
+    state::State, try_parse_cmd, Blend, ColorSpace, Keyboard, LFOS_NAME, PACKET_COUNT, PACKET_LEN,
+};
+
+// Only one USB context can have the keyboard claimed, so calls from several threads take turns
+static KEYBOARD: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// The same numbers the command line exits with, so callers can share one table of meanings
+fn status(result: LfosResult<()>) -> c_int {
+    let (code, message) = match result {
+        Ok(()) => (0, None),
+        Err(err) => (err.exit_code(), Some(err.to_string())),
+    };
+    // Messages never have a NUL in them, but if one did it would end up cut short instead of lost
+    let message = message.map(|message| CString::new(message.replace('\0', " ")).unwrap_or_default());
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    code
+}
+
+// Panics can't unwind into C, so they come back as a plain error instead
+fn guard(f: impl FnOnce() -> LfosResult<()>) -> c_int {
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        Err(LfosError::Io(std::io::Error::other("lights-for-omen-sequencer panicked")))
+    });
+    status(result)
+}
+
+unsafe fn read_json(json: *const c_char) -> LfosResult<String> {
+    if json.is_null() {
+        return Err(LfosError::MissingValue {
+            option: "json".to_string(),
+            expected: "a JSON object of keys and colors".to_string(),
+        });
+    }
+    CStr::from_ptr(json).to_str().map(str::to_string).map_err(|_| LfosError::InvalidValue {
+        option: "json".to_string(),
+        value: "<not UTF-8>".to_string(),
+        expected: "UTF-8 text".to_string(),
+    })
+}
+
+fn overrides(json: &str) -> LfosResult<HashMap<String, u32>> {
+    let mut overrides = HashMap::new();
+    apply_json_text(&get_lfos(), &mut overrides, "<json>", json, Blend::Replace, ColorSpace::Rgb, false)?;
+    Ok(overrides)
+}
+
+/// Applies a JSON object of keys and colors, like `{"all": "000000", "wasd": "ff0000"}`, to the keyboard and saves
+/// it as the current colors. Returns 0, or the exit code the command line would give for the same error.
+///
+/// # Safety
+///
+/// `json` must be NULL or point to a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn lfos_apply_json(json: *const c_char) -> c_int {
+    guard(|| {
+        let json = read_json(json)?;
+        let lfos = &get_lfos();
+        let overrides = overrides(&json)?;
+        let command = try_parse_cmd(lfos, &[LFOS_NAME.to_string()], &Config::load()?)?;
+        let table = build_lfos_table(lfos, &overrides, &command);
+
+        let _claimed = KEYBOARD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut keyboard = Keyboard::open(&command)?;
+        keyboard.try_write_table(&command, &table)?;
+        let state = State {
+            colors: resolve_colors(lfos, &overrides),
+            ..State::default()
+        };
+        save_state(&command, &state, Some(&keyboard));
+        Ok(())
+    })
+}
+
+/// Builds the packets `lfos_apply_json` would send, without touching the keyboard, into `out`, which must hold
+/// `lfos_table_len()` bytes. Returns 0, or the exit code the command line would give for the same error.
+///
+/// # Safety
+///
+/// `json` must be NULL or point to a NUL terminated string, and `out` must be NULL or point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lfos_table_json(json: *const c_char, out: *mut u8, len: usize) -> c_int {
+    guard(|| {
+        let json = read_json(json)?;
+        if out.is_null() || len < lfos_table_len() {
+            return Err(LfosError::InvalidValue {
+                option: "out".to_string(),
+                value: len.to_string(),
+                expected: format!("a buffer of at least {} bytes", lfos_table_len()),
+            });
+        }
+        let lfos = &get_lfos();
+        let command = try_parse_cmd(lfos, &[LFOS_NAME.to_string()], &Config::load()?)?;
+        let table = build_lfos_table(lfos, &overrides(&json)?, &command);
+        let out = std::slice::from_raw_parts_mut(out, len);
+        for (packet, chunk) in table.iter().zip(out.chunks_mut(PACKET_LEN)) {
+            chunk.copy_from_slice(packet);
+        }
+        Ok(())
+    })
+}
+
+/// How many bytes `lfos_table_json` writes: every packet sent to the keyboard, one after the other.
+#[no_mangle]
+pub extern "C" fn lfos_table_len() -> usize {
+    PACKET_COUNT * PACKET_LEN
+}
+
+/// The message for the last error on this thread, or NULL if the last call worked. It stays valid until the next
+/// call on the same thread.
+#[no_mangle]
+pub extern "C" fn lfos_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |message| message.as_ptr()))
+}
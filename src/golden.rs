@@ -0,0 +1,91 @@
+use std::io;
+
+use crate::{
+    decode_hex,
+    error::{LfosError, LfosResult, Problem},
+    to_hex,
+};
+
+// A golden file is the table as hex, one packet per line. Lines starting with # are kept as they are when the file
+// is updated, so it can say which profile it was made from
+fn parse(path: &str, text: &str) -> LfosResult<Vec<Vec<u8>>> {
+    let mut packets = Vec::new();
+    let mut problems = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match decode_hex(line) {
+            Some(packet) => packets.push(packet),
+            None => problems.push(Problem {
+                line: Some(index + 1),
+                key: None,
+                message: "expected a packet as hex".to_string(),
+            }),
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(LfosError::InvalidProfile {
+            name: path.to_string(),
+            problems,
+        });
+    }
+    Ok(packets)
+}
+
+// One line per byte that changed, so a slipped offset shows up as the exact bytes it moved
+fn diff(expected: &[Vec<u8>], table: &[Vec<u8>]) -> Vec<String> {
+    let mut differences = Vec::new();
+    for index in 0..expected.len().max(table.len()) {
+        let (expected, built) = match (expected.get(index), table.get(index)) {
+            (Some(expected), Some(built)) => (expected, built),
+            (Some(_), None) => {
+                differences.push(format!("packet {}: missing from the table", index));
+                continue;
+            }
+            (None, _) => {
+                differences.push(format!("packet {}: not in the golden file", index));
+                continue;
+            }
+        };
+        if expected.len() != built.len() {
+            differences.push(format!("packet {}: {} bytes, expected {}", index, built.len(), expected.len()));
+        }
+        for (byte, (expected, built)) in expected.iter().zip(built).enumerate() {
+            if expected != built {
+                differences.push(format!("packet {} byte {}: {:02x}, expected {:02x}", index, byte, built, expected));
+            }
+        }
+    }
+    differences
+}
+
+pub fn verify(path: &str, table: &[Vec<u8>], update: bool) -> LfosResult<()> {
+    let text = std::fs::read_to_string(path);
+    if update {
+        // Keep the comments at the top, they're what says how to make the file again
+        let mut lines: Vec<String> = text
+            .unwrap_or_default()
+            .lines()
+            .take_while(|line| line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        lines.extend(table.iter().map(|packet| to_hex(packet)));
+        std::fs::write(path, lines.join("\n") + "\n")?;
+        eprintln!("verify-golden: wrote {} packets to {}", table.len(), path);
+        return Ok(());
+    }
+
+    let text = text.map_err(|err| io::Error::new(err.kind(), format!("could not read golden file '{}': {}", path, err)))?;
+    let differences = diff(&parse(path, &text)?, table);
+    if !differences.is_empty() {
+        return Err(LfosError::GoldenMismatch {
+            path: path.to_string(),
+            differences,
+        });
+    }
+    println!("verify-golden: {} matches", path);
+    Ok(())
+}
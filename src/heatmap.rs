@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use crate::{
+    color::{gradient, ColorSpace},
+    error::{LfosError, LfosResult, Problem},
+    is_key, read_profile, resolve_key, LFOS,
+};
+
+// From blue for the least used keys, through cyan, green and yellow, up to red for the most used one
+pub const COLD_TO_HOT: [u32; 5] = [0x0000ff, 0x00ffff, 0x00ff00, 0xffff00, 0xff0000];
+
+// Splits "key count" lines on the last comma, colon, equals sign or whitespace, so "," and ":" still work as keys
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let separators = [',', ':', '='];
+    let (key, count) = line.trim().rsplit_once(|c: char| separators.contains(&c) || c.is_whitespace())?;
+    // "a: 250" splits on the space, which leaves the colon on the key
+    let key = match key.trim().trim_end_matches(separators).trim() {
+        "" => key.trim(),
+        trimmed => trimmed,
+    };
+    Some((key.trim_matches('"'), count.trim().trim_matches('"')))
+}
+
+// Colors every key by how often it was pressed, relative to the most pressed key. Keys missing from the stats stay off
+pub fn load(lfos: &LFOS, path: &str, stops: &[u32], space: ColorSpace) -> LfosResult<HashMap<String, u32>> {
+    let name = if path == "-" { "<stdin>" } else { path };
+    let text = read_profile(path)?;
+
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    let mut unknown = Vec::new();
+    let mut problems = Vec::new();
+    let mut first = true;
+    for (index, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        if line.trim().is_empty() {
+            continue;
+        }
+        let header = std::mem::take(&mut first);
+
+        let (key, count) = match split_line(line) {
+            Some(fields) => fields,
+            None => {
+                problems.push(Problem {
+                    line: Some(index + 1),
+                    key: None,
+                    message: format!("'{}' should be a key and a number of presses", line.trim()),
+                });
+                continue;
+            }
+        };
+        let count: u64 = match count.parse() {
+            Ok(count) => count,
+            // A first line like "key,count" is a header
+            Err(_) if header => continue,
+            Err(_) => {
+                problems.push(Problem {
+                    line: Some(index + 1),
+                    key: Some(key.to_string()),
+                    message: format!("'{}' is not a number of presses", count),
+                });
+                continue;
+            }
+        };
+
+        // Stats tools tend to write "A" or "Space", and may count keys this keyboard doesn't have
+        let lowercase = key.to_lowercase();
+        let resolved = resolve_key(lfos, &lowercase);
+        if resolved == "all" || !is_key(lfos, resolved) {
+            unknown.push(key.to_string());
+            continue;
+        }
+        *counts.entry(resolved.to_string()).or_default() += count;
+    }
+
+    if !problems.is_empty() {
+        return Err(LfosError::InvalidProfile {
+            name: name.to_string(),
+            problems,
+        });
+    }
+    if !unknown.is_empty() {
+        eprintln!("heatmap: skipped keys this keyboard doesn't have: {}", unknown.join(", "));
+    }
+
+    let most = counts.values().copied().max().unwrap_or(0).max(1);
+    let mut overrides: HashMap<String, u32> = counts
+        .into_iter()
+        .map(|(key, count)| (key, gradient(stops, count as f64 / most as f64, space)))
+        .collect();
+    overrides.insert("all".to_string(), 0x000000);
+    Ok(overrides)
+}
@@ -0,0 +1,4532 @@
+use rusb::{
+    Device, DeviceDescriptor, DeviceHandle, Direction, Result, TransferType, UsbContext,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Once},
+    io::{IsTerminal, Write},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use log::{debug, trace};
+
+#[cfg(feature = "audio")]
+mod audio;
+mod calibration;
+mod color;
+mod config;
+mod effect;
+pub mod error;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod golden;
+mod heatmap;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod replay;
+#[cfg(unix)]
+mod serve;
+#[cfg(feature = "screen")]
+mod screen;
+mod state;
+mod share;
+mod svg;
+mod theme;
+#[cfg(feature = "tui")]
+mod tui;
+
+use color::{gradient, hue_shift, named_color, nearest_color, Blend, ColorSpace};
+use effect::{Blink, Effect};
+use error::{LfosError, LfosResult, Problem, PERMISSION_HINT};
+use calibration::Calibration;
+use config::Config;
+use state::State;
+
+const LFOS_NAME: &str = env!("CARGO_PKG_NAME");
+const LFOS_VERSION: &str = env!("CARGO_PKG_VERSION");
+const LFOS_DEFAULT_PROFILE: &str = "LFOS_DEFAULT_PROFILE";
+const DEFAULT_POWER_BUDGET: u32 = 50;
+
+#[derive(Clone, Debug)]
+struct Endpoint {
+    config: u8,
+    iface: u8,
+    setting: u8,
+    address: u8,
+}
+
+// A keyboard that is there but can't be opened reports why, so that busy and missing can be told apart
+fn open_device<T: UsbContext>(
+    context: &mut T,
+    vid: u16,
+    pid: u16,
+) -> LfosResult<(Device<T>, DeviceDescriptor, DeviceHandle<T>)> {
+    let devices = match context.devices() {
+        Ok(d) => d,
+        Err(_) => return Err(LfosError::DeviceNotFound),
+    };
+
+    let mut error = LfosError::DeviceNotFound;
+    for device in devices.iter() {
+        let device_desc = match device.device_descriptor() {
+            Ok(d) => d,
+            Err(_) => continue,
+        };
+
+        if device_desc.vendor_id() == vid && device_desc.product_id() == pid {
+            match device.open() {
+                Ok(handle) => return Ok((device, device_desc, handle)),
+                Err(err) => error = open_error(err.into()),
+            }
+        }
+    }
+
+    Err(error)
+}
+
+// Opening and claiming fail the same way, and the two usual reasons get errors that say what to do about them
+fn open_error(err: LfosError) -> LfosError {
+    match err {
+        LfosError::Usb(rusb::Error::Busy) => LfosError::DeviceBusy { retries: 0 },
+        LfosError::Usb(rusb::Error::Access) => LfosError::AccessDenied,
+        err => err,
+    }
+}
+
+// An endpoint together with what the search for a writable one looks at
+#[derive(Clone, Debug)]
+struct EndpointInfo {
+    endpoint: Endpoint,
+    direction: Direction,
+    transfer: TransferType,
+}
+
+// The endpoints a device lists in its descriptors, behind a trait so the search can run on made up devices in tests
+trait UsbDevice {
+    fn endpoints(&self) -> Vec<EndpointInfo>;
+}
+
+impl<T: UsbContext> UsbDevice for Device<T> {
+    fn endpoints(&self) -> Vec<EndpointInfo> {
+        let configurations = self.device_descriptor().map_or(0, |desc| desc.num_configurations());
+        let mut endpoints = Vec::new();
+        for n in 0..configurations {
+            let config_desc = match self.config_descriptor(n) {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+
+            for interface in config_desc.interfaces() {
+                for interface_desc in interface.descriptors() {
+                    for endpoint_desc in interface_desc.endpoint_descriptors() {
+                        endpoints.push(EndpointInfo {
+                            endpoint: Endpoint {
+                                config: config_desc.number(),
+                                iface: interface_desc.interface_number(),
+                                setting: interface_desc.setting_number(),
+                                address: endpoint_desc.address(),
+                            },
+                            direction: endpoint_desc.direction(),
+                            transfer: endpoint_desc.transfer_type(),
+                        });
+                    }
+                }
+            }
+        }
+        endpoints
+    }
+}
+
+fn find_writable_endpoints(device: &impl UsbDevice, transfer_type: TransferType) -> LfosResult<Vec<Endpoint>> {
+    let endpoints: Vec<Endpoint> = device
+        .endpoints()
+        .into_iter()
+        .filter(|info| info.direction == Direction::Out && info.transfer == transfer_type)
+        .map(|info| info.endpoint)
+        .collect();
+    for endpoint in &endpoints {
+        trace!(
+            "Found writable endpoint at address {} on interface {}, alternate setting {}",
+            endpoint.address,
+            endpoint.iface,
+            endpoint.setting
+        );
+    }
+
+    match endpoints.is_empty() {
+        true => Err(LfosError::NoEndpoint {
+            transfer: transfer_name(transfer_type).to_string(),
+        }),
+        false => Ok(endpoints),
+    }
+}
+
+// Readies the interface of an endpoint for writing and keeps it claimed. Says whether a kernel driver had to be
+// detached for it, so release_endpoint knows to attach it again
+fn claim_endpoint<T: UsbContext>(handle: &mut DeviceHandle<T>, endpoint: &Endpoint, force_detach: bool) -> Result<bool> {
+    trace!("Claiming endpoint: {:?}", endpoint);
+
+    let has_kernel_driver = if force_detach {
+        match handle.detach_kernel_driver(endpoint.iface) {
+            Ok(()) => {
+                trace!(" - forced kernel driver detach succeeded");
+                true
+            }
+            Err(err) => {
+                trace!(" - forced kernel driver detach failed: {}", err);
+                false
+            }
+        }
+    } else {
+        match handle.kernel_driver_active(endpoint.iface) {
+            Ok(true) => {
+                handle.detach_kernel_driver(endpoint.iface).ok();
+                true
+            }
+            _ => false,
+        }
+    };
+
+    trace!(" - kernel driver? {}", has_kernel_driver);
+
+    match configure_endpoint(handle, endpoint) {
+        Ok(()) => Ok(has_kernel_driver),
+        Err(err) => {
+            release_endpoint(handle, endpoint, has_kernel_driver, true);
+            Err(err)
+        }
+    }
+}
+
+fn release_endpoint<T: UsbContext>(handle: &mut DeviceHandle<T>, endpoint: &Endpoint, has_kernel_driver: bool, reattach: bool) {
+    trace!("Releasing endpoint: {:?}", endpoint);
+    handle.release_interface(endpoint.iface).ok();
+    if has_kernel_driver && reattach {
+        handle.attach_kernel_driver(endpoint.iface).ok();
+    }
+}
+
+// Only writes, the interface has to be claimed already
+fn try_write_endpoint<T: UsbContext>(
+    handle: &DeviceHandle<T>,
+    endpoint: &Endpoint,
+    transfer_type: TransferType,
+    data: &[u8],
+) -> Result<()> {
+    trace!("Writing to endpoint: {:?}", endpoint);
+    let timeout = Duration::from_secs(1);
+    let len = match transfer_type {
+        TransferType::Interrupt => handle.write_interrupt(endpoint.address, data, timeout)?,
+        TransferType::Bulk => handle.write_bulk(endpoint.address, data, timeout)?,
+        _ => 0,
+    };
+    trace!(" - wrote: {} bytes", len);
+    Ok(())
+}
+
+// Claims the interface for one packet and lets it go again, for checking an endpoint before settling on it
+fn write_endpoint_once<T: UsbContext>(handle: &mut DeviceHandle<T>, endpoint: &Endpoint, command: &Command, data: &[u8]) -> Result<()> {
+    let has_kernel_driver = claim_endpoint(handle, endpoint, command.force_detach)?;
+    let written = try_write_endpoint(handle, endpoint, command.transfer, data);
+    release_endpoint(handle, endpoint, has_kernel_driver, command.reattach);
+    written
+}
+
+// A write that was refused needs the same fix as a keyboard that can't be opened, so it gets the same hint
+fn write_error(err: rusb::Error) -> LfosError {
+    match err {
+        rusb::Error::Access => LfosError::AccessDenied,
+        err => LfosError::Usb(err),
+    }
+}
+
+fn report_write_error(err: LfosError) {
+    match err {
+        // Writing fails like this every time, so the fix is only printed once
+        LfosError::AccessDenied => {
+            static HINT: Once = Once::new();
+            HINT.call_once(|| eprintln!("could not write to endpoint: {}", err));
+        }
+        err => eprintln!("could not write to endpoint: {}", err),
+    }
+}
+
+// Some firmware only takes the colors on an alternate setting other than the first one that has an endpoint
+fn pick_endpoint<T: UsbContext>(
+    handle: &mut DeviceHandle<T>,
+    mut candidates: Vec<Endpoint>,
+    command: &Command,
+    id: &str,
+) -> LfosResult<Endpoint> {
+    let no_endpoint = || LfosError::NoEndpoint {
+        transfer: transfer_name(command.transfer).to_string(),
+    };
+    if let Some(setting) = command.alt_setting {
+        candidates.retain(|endpoint| endpoint.setting == setting);
+    }
+    if candidates.len() <= 1 {
+        return candidates.into_iter().next().ok_or_else(no_endpoint);
+    }
+
+    // The setting that worked last time is trusted without trying the others again
+    let cached = State::load_device(id).ok().flatten().and_then(|state| state.alt_setting);
+    if let Some(index) = candidates.iter().position(|endpoint| Some(endpoint.setting) == cached) {
+        return Ok(candidates.swap_remove(index));
+    }
+
+    // The init packet on its own doesn't change any colors, so it's safe to test with
+    match candidates.iter().position(|endpoint| {
+        write_endpoint_once(handle, endpoint, command, &command.init).is_ok()
+    }) {
+        Some(index) => Ok(candidates.swap_remove(index)),
+        None => Ok(candidates.swap_remove(0)),
+    }
+}
+
+fn transfer_name(transfer: TransferType) -> &'static str {
+    match transfer {
+        TransferType::Bulk => "bulk",
+        _ => "interrupt",
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+fn capture_packet(path: &Path, data: &[u8]) {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let line = format!("{}.{:03} {}", time.as_secs(), time.subsec_millis(), to_hex(data));
+    if let Err(err) = append_line(path, &line) {
+        eprintln!("could not capture the packet to '{}': {}", path.display(), err);
+    }
+}
+
+// The calls that ready an interface for sending, behind a trait so the fallbacks can be checked against made up handles in tests
+trait UsbHandle {
+    fn set_active_configuration(&mut self, config: u8) -> Result<()>;
+    fn claim_interface(&mut self, iface: u8) -> Result<()>;
+    fn set_alternate_setting(&mut self, iface: u8, setting: u8) -> Result<()>;
+}
+
+impl<T: UsbContext> UsbHandle for DeviceHandle<T> {
+    fn set_active_configuration(&mut self, config: u8) -> Result<()> {
+        DeviceHandle::set_active_configuration(self, config)
+    }
+
+    fn claim_interface(&mut self, iface: u8) -> Result<()> {
+        DeviceHandle::claim_interface(self, iface)
+    }
+
+    fn set_alternate_setting(&mut self, iface: u8, setting: u8) -> Result<()> {
+        DeviceHandle::set_alternate_setting(self, iface, setting)
+    }
+}
+
+fn configure_endpoint(handle: &mut impl UsbHandle, endpoint: &Endpoint) -> Result<()> {
+    trace!(
+        "Configuring for sending, and claiming the interface. {:?}",
+        endpoint
+    );
+    handle.set_active_configuration(endpoint.config)?;
+    handle.claim_interface(endpoint.iface)?;
+    match handle.set_alternate_setting(endpoint.iface, endpoint.setting) {
+        // Some backends refuse the call for interfaces that only have the default setting, which is already in use
+        Err(err @ (rusb::Error::NotSupported | rusb::Error::InvalidParam)) if endpoint.setting == 0 => {
+            trace!(" - skipped setting the alternate setting: {}", err);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+const HEADER0: &str = "04000200fcea00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000";
+const HEADER1: &str = "05003c00";
+const HEADER2: &str = "05013c00";
+const HEADER3: &str = "05021800";
+const HEADER4: &str = "06003c00";
+const HEADER5: &str = "06013c00";
+const HEADER6: &str = "06021800";
+const HEADER7: &str = "07003c00";
+const HEADER8: &str = "07013c00";
+const HEADER9: &str = "07021800";
+const BODY0: &str = "ffffffffffffffffffffffffffff00ffffffffff00ffff00ffffffffff00ffffffffffffffffffffffffffffff0000ffffffffffff00ffff00ffff00";
+const BODY1: &str = "ffff0000ffffffffffffffff00ffff00ffff0000ffffffffff00ffffffffff00ffff0000ffffffffff00ffffff00ff00ffff0000ffffffffffffffff";
+const KEY_COUNT: usize = 142;
+const LEDS_PER_LINE: usize = 60;
+const PACKET_COUNT: usize = 10;
+const PACKET_LEN: usize = 64;
+const BODY2: &str = "ffffff00ffff0000ffffffffffffffffffff0000ffff0000000000000000000000000000000000000000000000000000000000000000000000000000";
+
+struct Line {
+    header: Vec<u8>,
+    leds: Vec<bool>,
+    ofset: u8,
+}
+
+// The packets never change, so they are decoded once instead of on every frame
+static INIT_PACKET: LazyLock<Vec<u8>> = LazyLock::new(|| decode_hex(HEADER0).expect("HEADER0 is hex"));
+static LINES: LazyLock<Vec<Line>> = LazyLock::new(|| {
+    let line = |header: &str, body: &str, ofset: u8| Line {
+        header: decode_hex(header).expect("headers are hex"),
+        leds: decode_hex(body).expect("masks are hex").iter().map(|byte| *byte != 0).collect(),
+        ofset,
+    };
+    vec![
+        line(HEADER1, BODY0, 16),
+        line(HEADER2, BODY1, 16),
+        line(HEADER3, BODY2, 16),
+        line(HEADER4, BODY0, 8),
+        line(HEADER5, BODY1, 8),
+        line(HEADER6, BODY2, 8),
+        line(HEADER7, BODY0, 0),
+        line(HEADER8, BODY1, 0),
+        line(HEADER9, BODY2, 0),
+    ]
+});
+
+// The interface each packet has to be written to, starting with the init packet and then in the order of
+// LINES. None is the interface picked for the keyboard, which takes every packet on the known firmware
+const PACKET_INTERFACES: [Option<u8>; PACKET_COUNT] = [None; PACKET_COUNT];
+
+// Packets are told apart by their header, since --apply-order and --only-channel change their order and count
+fn packet_interface(packet: &[u8]) -> Option<u8> {
+    if packet == INIT_PACKET.as_slice() {
+        return PACKET_INTERFACES[0];
+    }
+    let line = LINES.iter().position(|line| packet.starts_with(&line.header))?;
+    PACKET_INTERFACES[line + 1]
+}
+
+// Endpoints on the other interfaces named in PACKET_INTERFACES, so nothing has to be looked up while writing
+fn split_endpoints(candidates: &[Endpoint], transfer: TransferType) -> LfosResult<Vec<Endpoint>> {
+    let mut split: Vec<Endpoint> = Vec::new();
+    for iface in PACKET_INTERFACES.iter().flatten() {
+        if split.iter().any(|endpoint| endpoint.iface == *iface) {
+            continue;
+        }
+        match candidates.iter().find(|endpoint| endpoint.iface == *iface) {
+            Some(endpoint) => split.push(endpoint.clone()),
+            None => {
+                return Err(LfosError::NoEndpoint {
+                    transfer: transfer_name(transfer).to_string(),
+                })
+            }
+        }
+    }
+    Ok(split)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelOrder {
+    Rgb,
+    Bgr,
+    Grb,
+}
+
+impl ChannelOrder {
+    fn parse(value: &str) -> Option<ChannelOrder> {
+        match value {
+            "rgb" => Some(ChannelOrder::Rgb),
+            "bgr" => Some(ChannelOrder::Bgr),
+            "grb" => Some(ChannelOrder::Grb),
+            _ => None,
+        }
+    }
+
+    fn ofset(self, ofset: u8) -> u8 {
+        match (self, ofset) {
+            (ChannelOrder::Bgr, 16) => 0,
+            (ChannelOrder::Bgr, 0) => 16,
+            (ChannelOrder::Grb, 16) => 8,
+            (ChannelOrder::Grb, 8) => 16,
+            _ => ofset,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ApplyOrder {
+    Forward,
+    Reverse,
+}
+
+impl ApplyOrder {
+    fn parse(value: &str) -> Option<ApplyOrder> {
+        match value {
+            "forward" => Some(ApplyOrder::Forward),
+            "reverse" => Some(ApplyOrder::Reverse),
+            _ => None,
+        }
+    }
+
+    fn packets(self, table: &[Vec<u8>]) -> Vec<(usize, &[u8])> {
+        let mut packets: Vec<_> = table.iter().map(|packet| packet.as_slice()).enumerate().collect();
+        if self == ApplyOrder::Reverse {
+            packets.reverse();
+        }
+        packets
+    }
+}
+
+fn add_group(groups: &mut HashMap<String, Vec<String>>, name: &str, values: Vec<&str>) {
+    groups.insert(
+        name.to_string(),
+        values.iter().map(|e| e.to_string()).collect(),
+    );
+}
+
+fn get_key_groups() -> HashMap<String, Vec<String>> {
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    add_group(&mut groups, "pkeys", vec!["p1", "p2", "p3", "p4", "p5"]);
+    add_group(&mut groups, "fkeys", vec!["f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12"]);
+    add_group(&mut groups, "media", vec!["play", "stop", "playlast", "playnext"]);
+    add_group(&mut groups, "system", vec!["prtscrn", "sclock", "pause", "insert", "home", "pgup", "delete", "end", "pgdown"]);
+    add_group(&mut groups, "arrows", vec!["leftarrow", "rightarrow", "uparrow", "downarrow"]);
+    add_group(&mut groups, "modifiers", vec!["lshift", "rshift", "lcontrol", "rctrl", "lalt", "altgr", "fn", "windows"]);
+    add_group(&mut groups, "letters", vec!["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z"]);
+    add_group(&mut groups, "numpad", vec!["numlock", "numpad/", "numpad*", "numpad-", "numpad7", "numpad8", "numpad9", "numpad+", "numpad4", "numpad5", "numpad6", "numpad1", "numpad2", "numpad3", "numpad0", "numpad.", "numpadenter"]);
+
+    // Built from the groups above, so a key renamed there can't be missed here
+    let extras = [groups["media"].clone(), groups["pkeys"].clone()].concat();
+    groups.insert("extras".to_string(), extras);
+
+    for (name, keys) in get_finger_groups().into_iter().chain(get_row_groups()) {
+        add_group(&mut groups, name, keys);
+    }
+
+    groups
+}
+
+// The rows of keys that type characters, from the number row down, leaving out tab, shift and the like
+fn get_row_groups() -> Vec<(&'static str, Vec<&'static str>)> {
+    vec![
+        ("numberrow", vec!["\\", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "'", "«"]),
+        ("toprow", vec!["q", "w", "e", "r", "t", "y", "u", "i", "o", "p", "+", "´"]),
+        ("homerow", vec!["a", "s", "d", "f", "g", "h", "j", "k", "l", "ç", "º", "~"]),
+        ("bottomrow", vec!["<", "z", "x", "c", "v", "b", "n", "m", ",", ".", "-"]),
+    ]
+}
+
+// Touch typing fingers for the main block of the ISO layout, from left to right
+fn get_finger_groups() -> Vec<(&'static str, Vec<&'static str>)> {
+    vec![
+        ("pinky-left", vec!["\\", "1", "tab", "q", "capslock", "a", "lshift", "<", "z", "lcontrol"]),
+        ("ring-left", vec!["2", "w", "s", "x"]),
+        ("middle-left", vec!["3", "e", "d", "c"]),
+        ("index-left", vec!["4", "5", "r", "t", "f", "g", "v", "b"]),
+        ("thumbs", vec!["lalt", "space", "altgr"]),
+        ("index-right", vec!["6", "7", "y", "u", "h", "j", "n", "m"]),
+        ("middle-right", vec!["8", "i", "k", ","]),
+        ("ring-right", vec!["9", "o", "l", "."]),
+        ("pinky-right", vec!["0", "'", "«", "del", "p", "+", "´", "enter", "ç", "º", "~", "-", "rshift"]),
+    ]
+}
+
+pub fn get_keys() -> Vec<&'static str> {
+    vec![
+        "esc",
+        "\\",
+        "tab",
+        "capslock",
+        "lshift",
+        "lcontrol",
+        "f12",
+        "«",
+        "f9",
+        "9",
+        "o",
+        "l",
+        ",",
+        "<",
+        "????",
+        "leftarrow",
+        "f1",
+        "1",
+        "q",
+        "a",
+        "????",
+        "windows",
+        "prtscrn",
+        "????",
+        "f10",
+        "0",
+        "p",
+        "ç",
+        ".",
+        "????",
+        "enter",
+        "downarrow",
+        "f2",
+        "2",
+        "w",
+        "s",
+        "z",
+        "lalt",
+        "sclock",
+        "del",
+        "f11",
+        "'",
+        "+",
+        "º",
+        "-",
+        "????",
+        "????",
+        "rightarrow",
+        "f3",
+        "3",
+        "e",
+        "d",
+        "x",
+        "????",
+        "pause",
+        "delete",
+        "????",
+        "numpad7",
+        "p1",
+        "????",
+        "numlock",
+        "numpad6",
+        "????",
+        "????",
+        "f4",
+        "4",
+        "r",
+        "f",
+        "c",
+        // Index 69 is the only "????" whose byte is lit in the BODY masks, and it sits
+        // in the bottom row slot right below "c" (like "windows", "lalt" and "altgr")
+        "space",
+        "insert",
+        "end",
+        "????",
+        "numpad8",
+        "p2",
+        "????",
+        "numpad/",
+        "numpad1",
+        "????",
+        "????",
+        "f5",
+        "5",
+        "t",
+        "g",
+        "v",
+        "????",
+        "home",
+        "pgdown",
+        "stop",
+        "numpad9",
+        "p3",
+        "????",
+        "numpad*",
+        "numpad2",
+        "????",
+        "????",
+        "f6",
+        "6",
+        "y",
+        "h",
+        "b",
+        "????",
+        "pgup",
+        "rshift",
+        "playlast",
+        "????",
+        "p4",
+        "????",
+        "numpad-",
+        "numpad3",
+        "????",
+        "????",
+        "f7",
+        "7",
+        "u",
+        "j",
+        "n",
+        "altgr",
+        "´",
+        "rctrl",
+        "play",
+        "numpad4",
+        "p5",
+        "????",
+        "numpad+",
+        "numpad0",
+        "????",
+        "????",
+        "f8",
+        "8",
+        "i",
+        "k",
+        "m",
+        "fn",
+        "~",
+        "uparrow",
+        "playnext",
+        "numpad5",
+        "????",
+        "????",
+        "numpadenter",
+        "numpad.",
+    ]
+}
+
+fn get_key_positions() -> HashMap<&'static str, (u8, u8)> {
+    let rows: [&[(&str, u8)]; 6] = [
+        &[
+            ("esc", 1), ("f1", 3), ("f2", 4), ("f3", 5), ("f4", 6), ("f5", 8), ("f6", 9),
+            ("f7", 10), ("f8", 11), ("f9", 12), ("f10", 13), ("f11", 14), ("f12", 15),
+            ("prtscrn", 16), ("sclock", 17), ("pause", 18),
+            ("playlast", 19), ("play", 20), ("stop", 21), ("playnext", 22),
+        ],
+        &[
+            ("p1", 0), ("\\", 1), ("1", 2), ("2", 3), ("3", 4), ("4", 5), ("5", 6), ("6", 7),
+            ("7", 8), ("8", 9), ("9", 10), ("0", 11), ("'", 12), ("«", 13), ("del", 14),
+            ("insert", 16), ("home", 17), ("pgup", 18),
+            ("numlock", 19), ("numpad/", 20), ("numpad*", 21), ("numpad-", 22),
+        ],
+        &[
+            ("p2", 0), ("tab", 1), ("q", 2), ("w", 3), ("e", 4), ("r", 5), ("t", 6), ("y", 7),
+            ("u", 8), ("i", 9), ("o", 10), ("p", 11), ("+", 12), ("´", 13), ("enter", 14),
+            ("delete", 16), ("end", 17), ("pgdown", 18),
+            ("numpad7", 19), ("numpad8", 20), ("numpad9", 21), ("numpad+", 22),
+        ],
+        &[
+            ("p3", 0), ("capslock", 1), ("a", 2), ("s", 3), ("d", 4), ("f", 5), ("g", 6),
+            ("h", 7), ("j", 8), ("k", 9), ("l", 10), ("ç", 11), ("º", 12), ("~", 13),
+            ("numpad4", 19), ("numpad5", 20), ("numpad6", 21),
+        ],
+        &[
+            ("p4", 0), ("lshift", 1), ("<", 2), ("z", 3), ("x", 4), ("c", 5), ("v", 6),
+            ("b", 7), ("n", 8), ("m", 9), (",", 10), (".", 11), ("-", 12), ("rshift", 13),
+            ("uparrow", 17),
+            ("numpad1", 19), ("numpad2", 20), ("numpad3", 21), ("numpadenter", 22),
+        ],
+        &[
+            ("p5", 0), ("lcontrol", 1), ("windows", 2), ("lalt", 3), ("space", 7),
+            ("altgr", 11), ("fn", 12), ("rctrl", 13),
+            ("leftarrow", 16), ("downarrow", 17), ("rightarrow", 18),
+            ("numpad0", 19), ("numpad.", 21),
+        ],
+    ];
+
+    let mut positions = HashMap::new();
+    for (row, keys) in rows.iter().enumerate() {
+        for (key, col) in keys.iter() {
+            positions.insert(*key, (row as u8, *col));
+        }
+    }
+
+    positions
+}
+
+fn get_key_aliases() -> HashMap<&'static str, &'static str> {
+    HashMap::from([
+        ("escape", "esc"),
+        ("ctrl", "lcontrol"),
+        ("control", "lcontrol"),
+        ("lctrl", "lcontrol"),
+        ("rcontrol", "rctrl"),
+        ("win", "windows"),
+        ("super", "windows"),
+        ("return", "enter"),
+        ("spacebar", "space"),
+        ("backspace", "del"),
+        ("shift", "lshift"),
+        ("alt", "lalt"),
+        ("left", "leftarrow"),
+        ("right", "rightarrow"),
+        ("up", "uparrow"),
+        ("down", "downarrow"),
+        // The symbol keys are named after their printed labels, which many terminals can't type or need quoting for
+        ("guillemet", "«"),
+        ("ccedilla", "ç"),
+        ("masculine-ordinal", "º"),
+        ("acute", "´"),
+        ("tilde", "~"),
+        ("backslash", "\\"),
+        ("apostrophe", "'"),
+        ("plus", "+"),
+        ("minus", "-"),
+        ("less", "<"),
+        ("comma", ","),
+        ("period", "."),
+    ])
+}
+
+// USB HID usage IDs from the Keyboard/Keypad page (0x07), by the position of the key they stand for.
+// The media keys are on the Consumer page and the p1-p5 / fn keys have no usage, so none of them are here
+fn get_hid_usages() -> HashMap<u8, &'static str> {
+    let letters = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m"]
+        .into_iter()
+        .chain(["n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z"]);
+    let digits = ["1", "2", "3", "4", "5", "6", "7", "8", "9", "0"];
+    let fkeys = ["f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12"];
+    let numpad = ["numpad1", "numpad2", "numpad3", "numpad4", "numpad5", "numpad6", "numpad7", "numpad8", "numpad9"];
+
+    let mut usages: HashMap<u8, &'static str> = HashMap::new();
+    usages.extend((0x04..).zip(letters));
+    usages.extend((0x1e..).zip(digits));
+    usages.extend((0x3a..).zip(fkeys));
+    usages.extend((0x59..).zip(numpad));
+    usages.extend([
+        (0x28, "enter"),
+        (0x29, "esc"),
+        (0x2a, "del"),
+        (0x2b, "tab"),
+        (0x2c, "space"),
+        (0x2d, "'"),
+        (0x2e, "«"),
+        (0x2f, "+"),
+        (0x30, "´"),
+        (0x32, "~"),
+        (0x33, "ç"),
+        (0x34, "º"),
+        (0x35, "\\"),
+        (0x36, ","),
+        (0x37, "."),
+        (0x38, "-"),
+        (0x39, "capslock"),
+        (0x46, "prtscrn"),
+        (0x47, "sclock"),
+        (0x48, "pause"),
+        (0x49, "insert"),
+        (0x4a, "home"),
+        (0x4b, "pgup"),
+        (0x4c, "delete"),
+        (0x4d, "end"),
+        (0x4e, "pgdown"),
+        (0x4f, "rightarrow"),
+        (0x50, "leftarrow"),
+        (0x51, "downarrow"),
+        (0x52, "uparrow"),
+        (0x53, "numlock"),
+        (0x54, "numpad/"),
+        (0x55, "numpad*"),
+        (0x56, "numpad-"),
+        (0x57, "numpad+"),
+        (0x58, "numpadenter"),
+        (0x62, "numpad0"),
+        (0x63, "numpad."),
+        (0x64, "<"),
+        (0xe0, "lcontrol"),
+        (0xe1, "lshift"),
+        (0xe2, "lalt"),
+        (0xe3, "windows"),
+        (0xe4, "rctrl"),
+        (0xe5, "rshift"),
+        (0xe6, "altgr"),
+    ]);
+    usages
+}
+
+fn color_component(color: u32, ofset: u8) -> u8 {
+    (color >> ofset & 0xff) as u8
+}
+
+#[allow(dead_code)]
+fn get_color(keys: &[&str], i: usize, ofset: u8) -> u8 {
+    if i < keys.len() {
+        return color_component(0xff0000, ofset);
+    } else if i == keys.len() {
+        return color_component(0xffffff, ofset);
+    }
+
+    color_component(0x000000, ofset)
+}
+
+#[allow(clippy::upper_case_acronyms)]
+struct LFOS {
+    groups: HashMap<String, Vec<String>>,
+    keys: Vec<&'static str>,
+    aliases: HashMap<&'static str, &'static str>,
+    positions: HashMap<&'static str, (u8, u8)>,
+    usages: HashMap<u8, &'static str>,
+}
+
+fn get_lfos() -> LFOS {
+    let keys = get_keys();
+    let groups = get_key_groups();
+    let aliases = get_key_aliases();
+    let positions = get_key_positions();
+    let usages = get_hid_usages();
+    LFOS {
+        keys,
+        groups,
+        aliases,
+        positions,
+        usages,
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for j in 0..b.len() {
+            let cost = if ca == b[j] { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn suggest_key<'a>(lfos: &'a LFOS, name: &str) -> Option<&'a str> {
+    let keys = lfos.keys.iter().copied().filter(|key| *key != "????");
+    let groups = lfos.groups.keys().map(|group| group.as_str());
+    let aliases = lfos.aliases.keys().copied();
+
+    keys.chain(groups)
+        .chain(aliases)
+        .chain(std::iter::once("all"))
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// Parses the usage out of "hid:0x29", or "hid:41" in decimal
+fn hid_usage(name: &str) -> Option<u8> {
+    let usage = name.strip_prefix("hid:")?;
+    match usage.strip_prefix("0x").or_else(|| usage.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => usage.parse().ok(),
+    }
+}
+
+fn resolve_key<'a>(lfos: &LFOS, name: &'a str) -> &'a str {
+    if let Some(key) = hid_usage(name).and_then(|usage| lfos.usages.get(&usage)) {
+        return key;
+    }
+    match lfos.aliases.get(name) {
+        Some(key) => key,
+        None => name,
+    }
+}
+
+fn show_usage(lfos: &LFOS) {
+    println!("Usage: {0} [options] [key|group] [color] ...", LFOS_NAME);
+    println!("       {0} [options] solid [color] [key|group] [color] ...", LFOS_NAME);
+    println!("example: {0} solid ff0000\nexample: {0} solid ff0000 pkeys 00ff00 home 00ff00", LFOS_NAME);
+    println!("example: {0} fkeys grad:ff0000,00ff00,0000ff", LFOS_NAME);
+    println!("example: {0} fkeys+arrows ff0000 numpad-numpad0 00ff00", LFOS_NAME);
+
+    println!("Commands:");
+    println!("\tsolid <color>: set every key to one color");
+    println!("\ttheme <name|daily> [--seed <n>]: apply a ready made theme ({}), or a different one every day", theme::names().join(", "));
+    println!("\ttoggle <key|group>...: turn keys off, or back on to the color they had before");
+    println!("\tclear <key|group>... [--merge]: turn keys off, and every other key white or, with --merge, as saved");
+    println!("\tcopy <key> <key|group>...: give keys the saved color of another key");
+    println!("\thue-shift <degrees>: rotate the hue of the saved colors, or of --profile");
+    println!("\tmqtt --broker <url> [--topic <prefix>]: keep running and apply the colors published to <prefix>/set, publishing them to <prefix>/state (needs the mqtt feature)");
+    println!("\tserve [--pipe <path>]: keep running and apply every profile written to a named pipe (Unix only)");
+    println!("\ttui: pick colors for each key interactively (needs the tui feature)");
+    println!("\texport [key|group] [color] ...: print the saved colors, or the given ones, as a profile");
+    println!("\tshare [key|group] [color] ...: print the saved colors, or the given ones, as one line to paste into apply-share");
+    println!("\tapply-share <blob>: apply colors printed by share");
+    println!("\theatmap <path> [--low <color>] [--high <color>]: color keys from cold to hot by how often they were pressed");
+    println!("\tsvg <path> [key|group] [color] ...: draw the saved colors, or the given ones, as an SVG keyboard");
+    println!("\tnew-profile <path> [--force]: write a commented profile listing every group and key, to start from");
+    println!("\tvalidate <path>: check a profile for mistakes without touching the keyboard");
+    println!("\teffect cycle [key|group]... [--speed <turns/s>] [--offset <degrees>]: run a rainbow across the keyboard, or only some keys, until Ctrl-C");
+    println!("\teffect matrix <color> [--speed <rows/s>] [--density <0-1>]: drops of color falling down the keyboard until Ctrl-C");
+    println!("\teffect wander <color> <color>... [--speed <changes/s>] [--seed <n>]: let every key drift between the colors on its own until Ctrl-C");
+    println!("\teffect palette-breathe <color> <color>... [--hold-ms <n>] [--fade-ms <n>]: crossfade the whole keyboard through the colors until Ctrl-C");
+    println!("\teffect audio [key|group]... [--low <color>] [--high <color>]: follow the loudness of the audio until Ctrl-C (needs the audio feature)");
+    println!("\taccent <key|group> --from-screen [--interval-ms <n>]: color keys with the average color of the screen (needs the screen feature)");
+    println!("\tpulse <key|group> <color> [--duration-ms <n>]: flash keys up to a color and back once, as a notification");
+    println!("\tpick <key|group>...: type colors for keys one after another, each applied right away, until done");
+    println!("\tindex <key>...: show where a key is in the key table and which packet bytes carry its color");
+    println!("\tcolors: list the color names that can be used instead of hex, with a swatch on color terminals");
+    println!("\tlayout: print the size of the key grid and the number of LEDs, with --format json also where every key is, for frontends");
+    println!("\tinfo: show the keyboard's USB details and which keyboards have saved colors");
+    println!("\trestore: apply the colors last saved for the connected keyboard");
+    println!("\tbench-apply <n> [key|group] [color] ...: apply the colors n times and print how long each apply took");
+    println!("\treplay <path> [--loop]: send the packets of a --capture log again, with the same pauses, and with --loop until Ctrl-C");
+    println!("\tdoctor: check that the keyboard can be found, opened and written to, with hints for what fails");
+    println!("\tselftest: check the internal key, group and packet tables");
+
+    println!("Options:");
+    println!("\t-h, --help: show this message");
+    println!("\t-v, --version: show the version");
+    println!("\t--delay-ms <n>: wait n milliseconds between packets, for unreliable USB hubs");
+    println!("\t--profile <path>: apply the colors in a TOML profile, use - to read it from stdin, give it again to layer more profiles on top");
+    println!("\t--csv <path>: apply the colors in a CSV file of key,color rows, like --profile");
+    println!("\t--highlight-keymap <path>: light the keys listed in a file and dim every other key, to show the controls of a game or app");
+    println!("\t--bound-color <color>, --unbound-color <color>: colors for --highlight-keymap (default ffffff and 202020)");
+    println!("\t--transfer <interrupt|bulk>: how packets are written to the keyboard (default interrupt)");
+    println!("\t--alt-setting <n>: use the endpoint on this alternate setting of the interface, instead of finding the one that works");
+    println!("\t--force-detach: always detach the kernel driver, for systems where checking for it fails");
+    println!("\t--no-reattach: leave the kernel driver detached after writing, for faster repeated calls");
+    println!("\t--persist, --no-persist: save the applied colors and read them back (default), or leave the data directory alone");
+    println!("\t--hold: apply the colors, then keep running so later calls skip the USB setup (Unix only)");
+    println!("\t--json-errors: print errors to stderr as a JSON object, for tools");
+    println!("\t--channel-order <rgb|bgr|grb>: color channel order expected by the keyboard (default rgb)");
+    println!("\t--interpolate <rgb|oklab>: color space for gradients, fades and heatmaps, oklab looks smoother (default rgb)");
+    println!("\t--only <key|group>,...: light only these keys with the colors they end up with, and turn every other key off");
+    println!("\t--blend <replace|add|max|average>: how a color combines with an earlier one for the same key (default replace)");
+    println!("\t--format <text|json>: output format for validate and layout, json also prints errors as JSON");
+    println!("\t--reset-on-exit <color|off>: set every key to this color when the program exits, even on Ctrl-C");
+    println!("\t--capture <path>: append every packet sent to the keyboard to a log file");
+    println!("\t--names: add the nearest named color to each color that export prints");
+    println!("\t--stdin-json: read a JSON object of keys and colors from stdin and apply it");
+    println!("\t--continue-on-error: skip keys and colors that can't be used, with a warning, and apply the rest");
+    println!("\t--key-size <px>, --gap <px>: size of each key and the space between them for svg (default 48 and 4)");
+    println!("\t--interval-ms <n>: with accent, sample the screen again every n milliseconds until Ctrl-C");
+    println!("\t--fps <n>: frames per second for effects (default 30)");
+    println!("\t--calibration <path>: scale the channels of single keys to even out uneven LEDs, overrides the config file");
+    println!("\t--brightness <percent>: dim every key, overrides the brightness in the config file (default 100)");
+    println!("\t--eco: dim every color evenly when the whole board would be brighter than the power budget");
+    println!("\t--power-budget <percent>: brightness allowed by --eco, as a percentage of every key at full white (default 50)");
+    println!("\t--confirm: show how many keys will change and ask before applying, when run in a terminal");
+    println!("\t--since-state: before applying, print how many keys differ from the saved colors");
+    println!("\t--start-black: turn every key off before applying, for keys that keep a stale color (sends twice the packets)");
+    println!("\t--show-overrides: print the color each key ends up with, as a profile, before applying");
+    println!("\t--dry-run: print the packets that would be sent instead of sending them");
+    println!("\t--retry-open <n>: when the keyboard is busy, try to open it again up to n times, waiting longer each time");
+    println!("\t--max-packets <n>: never send more than this many packets per table (default 10)");
+    println!("\t--simulate-latency <ms>: with --dry-run, wait this long per packet as if it was sent to the keyboard");
+    println!("\t--print-table-hex: like --dry-run, but print all packets as one line of hex, for diffing");
+    println!("\t--output-file <path>: write the packets from --dry-run or --print-table-hex to a file instead");
+    println!("\t--apply-order <forward|reverse>: order the packets are sent in, for firmware debugging (default forward)");
+    println!("Firmware debugging, not needed with the known firmware:");
+    println!("\t--init-hex <hex>: send this packet instead of the usual init packet, padded with zeros to {} bytes", PACKET_LEN);
+    println!("Environment:");
+    println!("\t{}: profile to apply when no keys, colors or --profile are given", LFOS_DEFAULT_PROFILE);
+
+    let fingers = get_finger_groups();
+    let rows = get_row_groups();
+    println!("Groups:\n\tall: all keys");
+    for (key, value) in &lfos.groups {
+        if !fingers.iter().chain(&rows).any(|(name, _)| name == key) {
+            println!("\t{}: {}", key, value.join(", "));
+        }
+    }
+    println!("Typing rows:");
+    for (name, _) in &rows {
+        println!("\t{}: {}", name, lfos.groups[*name].join(", "));
+    }
+    println!("Finger zones:");
+    for (name, _) in &fingers {
+        println!("\t{}: {}", name, lfos.groups[*name].join(", "));
+    }
+
+    let mut sorted_aliases: Vec<_> = lfos.aliases.iter().collect();
+    sorted_aliases.sort();
+    println!("Aliases:");
+    for (alias, key) in sorted_aliases {
+        println!("\t{}: {}", alias, key);
+    }
+
+    let mut sorted_keys = lfos.keys.clone();
+    sorted_keys.sort();
+    println!("Keys:");
+    for key in sorted_keys {
+        if key != "????" {
+            println!("\t{}", key);
+        }
+    }
+
+    std::process::exit(0);
+}
+
+fn show_version() {
+    println!("{} {}", LFOS_NAME, LFOS_VERSION);
+    std::process::exit(0);
+}
+
+fn show_help_or_version(lfos: &LFOS, args: &[String]) {
+    for arg in args {
+        if arg == "-h" || arg == "--help" {
+            show_usage(lfos);
+        }
+        if arg == "-v" || arg == "--version" {
+            show_version();
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Action {
+    Apply,
+    SelfTest,
+    Toggle(Vec<String>),
+    Clear(Vec<String>),
+    HueShift(f64),
+    Serve(PathBuf),
+    Mqtt { broker: String, topic: String },
+    Tui,
+    Validate(String),
+    Export,
+    Share,
+    Svg { path: String, key_size: u32, gap: u32 },
+    Info,
+    Doctor,
+    Restore,
+    BenchApply(u32),
+    Replay { path: String, looped: bool },
+    VerifyGolden { path: String, update: bool },
+    Effect(Effect),
+    Accent { keys: Vec<String>, interval: Option<Duration> },
+    Audio { keys: Vec<String>, low: u32, high: u32 },
+    Layout,
+    Colors,
+    Pick(Vec<String>),
+    Pulse { keys: Vec<String>, color: u32, duration: Duration },
+    NewProfile { path: String, force: bool },
+    Copy { source: String, keys: Vec<String> },
+    Index(Vec<String>),
+}
+
+impl Action {
+    fn uses_keyboard(&self) -> bool {
+        !matches!(
+            self,
+            Action::SelfTest
+                | Action::Validate(_)
+                | Action::Export
+                | Action::Share
+                | Action::Svg { .. }
+                | Action::VerifyGolden { .. }
+                | Action::Info
+                | Action::Doctor
+                | Action::Layout
+                | Action::Colors
+                | Action::NewProfile { .. }
+                | Action::Index(_)
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    fn parse(value: &str) -> Option<OutputFormat> {
+        match value {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Command {
+    action: Action,
+    overrides: HashMap<String, u32>,
+    delay: Duration,
+    force_detach: bool,
+    reattach: bool,
+    persist: bool,
+    channels: ChannelOrder,
+    order: ApplyOrder,
+    blend: Blend,
+    interpolation: ColorSpace,
+    format: OutputFormat,
+    reset: Option<u32>,
+    capture: Option<PathBuf>,
+    names: bool,
+    power_budget: Option<u32>,
+    table_hex: bool,
+    latency: Duration,
+    transfer: TransferType,
+    only_channel: Option<u8>,
+    alt_setting: Option<u8>,
+    output_file: Option<PathBuf>,
+    max_packets: usize,
+    init: Vec<u8>,
+    retry_open: u32,
+    brightness: u32,
+    calibration: Option<Calibration>,
+    fps: u32,
+    hold: bool,
+    dry_run: bool,
+    start_black: bool,
+    since_state: bool,
+    confirm: bool,
+    show_overrides: bool,
+    // Options given on the command line that a running helper wouldn't use, since it sends with its own
+    #[cfg_attr(not(unix), allow(dead_code))]
+    unshared: Vec<&'static str>,
+}
+
+fn parse_color(color: &str) -> LfosResult<u32> {
+    let invalid = |reason: String| LfosError::InvalidColor {
+        color: color.to_string(),
+        reason,
+    };
+    if let Some(color) = named_color(color) {
+        return Ok(color);
+    }
+
+    if let Some(channels) = color.strip_prefix("rgb(").and_then(|rest| rest.strip_suffix(')')) {
+        let channels: Vec<&str> = channels.split(',').map(str::trim).collect();
+        if channels.len() != 3 {
+            return Err(invalid("rgb() needs three numbers, like rgb(255,128,0)".to_string()));
+        }
+        return channels.iter().try_fold(0, |rgb, channel| {
+            let value: u8 = channel
+                .parse()
+                .map_err(|_| invalid(format!("'{}' is not a number from 0 to 255", channel)))?;
+            Ok(rgb << 8 | value as u32)
+        });
+    }
+
+    // Fractions from 0 to 1, as shaders and color libraries give them. Values past either end are clamped
+    if let Some(channels) = color.strip_prefix("f:") {
+        let channels: Vec<&str> = channels.split(',').map(str::trim).collect();
+        if channels.len() != 3 {
+            return Err(invalid("f: needs three numbers from 0 to 1, like f:1,0.5,0".to_string()));
+        }
+        return channels.iter().try_fold(0, |rgb, channel| {
+            let value: f64 = channel
+                .parse()
+                .ok()
+                .filter(|value: &f64| !value.is_nan())
+                .ok_or_else(|| invalid(format!("'{}' is not a number from 0 to 1", channel)))?;
+            Ok(rgb << 8 | (value.clamp(0.0, 1.0) * 255.0).round() as u32)
+        });
+    }
+
+    // Accept the prefixes used by CSS and C as well as bare hex
+    let hex = ["#", "0x", "0X"]
+        .iter()
+        .find_map(|prefix| color.strip_prefix(prefix))
+        .unwrap_or(color);
+    if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid("expected a name, 6 hex digits like ff8000, rgb(r,g,b) or f:r,g,b".to_string()));
+    }
+    match hex.len() {
+        6 => u32::from_str_radix(hex, 16).map_err(|err| invalid(err.to_string())),
+        // CSS shorthand, where #f80 is #ff8800
+        3 if color.starts_with('#') => Ok(hex.chars().fold(0, |rgb, digit| (rgb << 8) | (digit.to_digit(16).unwrap_or_default() * 0x11))),
+        // Anything longer is most likely a decimal number, or has an alpha channel the keyboard can't show
+        _ => Err(invalid(format!(
+            "expected 6 hex digits like ff8000, got {}; for decimal channels use rgb(r,g,b)",
+            hex.len()
+        ))),
+    }
+}
+
+// Splits gradient stops on the commas that aren't inside rgb(...) or between the numbers of f:r,g,b
+fn split_stops(stops: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in stops.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 && !(stops[start..i].starts_with("f:") && stops[start..i].matches(',').count() < 2) => {
+                parts.push(&stops[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(&stops[start..]);
+    parts
+}
+
+fn is_key(lfos: &LFOS, key: &str) -> bool {
+    key == "all" || (key != "????" && lfos.keys.contains(&key))
+}
+
+fn unknown_key(lfos: &LFOS, key: &str) -> LfosError {
+    if let Some(usage) = key.strip_prefix("hid:") {
+        return LfosError::InvalidValue {
+            option: "hid".to_string(),
+            value: usage.to_string(),
+            expected: "the usage ID of a key on the Keyboard/Keypad page (0x07), like hid:0x29 for esc".to_string(),
+        };
+    }
+    LfosError::UnknownKey {
+        key: key.to_string(),
+        suggestion: suggest_key(lfos, key).map(str::to_string),
+    }
+}
+
+// Splits "fkeys+arrows" or "numpad-numpad0" into the names in it, each with the operator in front of it. Keys like
+// "-" and "numpad+" have an operator in their name, so every way of splitting is tried, longest names first
+fn split_combo<'a>(lfos: &LFOS, text: &'a str, operator: char) -> Option<Vec<(char, &'a str)>> {
+    for end in (1..=text.len()).rev().filter(|end| text.is_char_boundary(*end)) {
+        let name = &text[..end];
+        if expand_name(lfos, name).is_err() {
+            continue;
+        }
+        let mut rest = text[end..].chars();
+        let next = match rest.next() {
+            None => return Some(vec![(operator, name)]),
+            Some(next @ ('+' | '-')) => next,
+            Some(_) => continue,
+        };
+        if let Some(mut names) = split_combo(lfos, rest.as_str(), next) {
+            names.insert(0, (operator, name));
+            return Some(names);
+        }
+    }
+    None
+}
+
+// Adds or takes away the keys of each name from left to right, keeping the order they were added in
+fn expand_combo(lfos: &LFOS, text: &str) -> LfosResult<Vec<String>> {
+    let names = match split_combo(lfos, text, '+') {
+        Some(names) => names,
+        None => {
+            let unknown = text.split(['+', '-']).find(|name| expand_name(lfos, name).is_err()).unwrap_or(text);
+            return Err(unknown_key(lfos, resolve_key(lfos, unknown)));
+        }
+    };
+
+    let mut keys: Vec<String> = Vec::new();
+    for (operator, name) in names {
+        let members = expand_name(lfos, name)?;
+        match operator {
+            '+' => keys.extend(members.into_iter().filter(|key| !keys.contains(key)).collect::<Vec<_>>()),
+            _ => keys.retain(|key| !members.contains(key)),
+        }
+    }
+    Ok(keys)
+}
+
+fn expand_keys(lfos: &LFOS, name: &str) -> LfosResult<Vec<String>> {
+    match expand_name(lfos, name) {
+        Err(LfosError::UnknownKey { .. }) if name.len() > 1 && name.contains(['+', '-']) => expand_combo(lfos, name),
+        expanded => expanded,
+    }
+}
+
+fn expand_name(lfos: &LFOS, name: &str) -> LfosResult<Vec<String>> {
+    let key = resolve_key(lfos, name);
+    if let Some(members) = lfos.groups.get(key) {
+        Ok(members.clone())
+    } else if key == "all" {
+        Ok(lfos
+            .keys
+            .iter()
+            .filter(|key| **key != "????")
+            .map(|key| key.to_string())
+            .collect())
+    } else if is_key(lfos, key) {
+        Ok(vec![key.to_string()])
+    } else {
+        Err(unknown_key(lfos, key))
+    }
+}
+
+fn collect_keys(lfos: &LFOS, command: &str, names: &[&String]) -> LfosResult<Vec<String>> {
+    if names.is_empty() {
+        return Err(LfosError::MissingValue {
+            option: command.to_string(),
+            expected: "one or more keys or groups".to_string(),
+        });
+    }
+
+    let mut keys = Vec::new();
+    for name in names {
+        for key in expand_keys(lfos, name)? {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+fn apply_override(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    name: &str,
+    value: u32,
+    blend: Blend,
+) -> LfosResult<()> {
+    let key = resolve_key(lfos, name);
+    if let Some(values) = lfos.groups.get(key) {
+        for val in values {
+            blend.insert(overrides, val, value);
+        }
+    } else if is_key(lfos, key) {
+        blend.insert(overrides, key, value);
+    } else {
+        for key in expand_keys(lfos, name)? {
+            blend.insert(overrides, &key, value);
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_gradient(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    name: &str,
+    color: &str,
+    stops: &str,
+    blend: Blend,
+    space: ColorSpace,
+) -> LfosResult<()> {
+    let invalid = |reason: String| LfosError::InvalidColor {
+        color: color.to_string(),
+        reason,
+    };
+    let stops = split_stops(stops)
+        .into_iter()
+        .map(parse_color)
+        .collect::<LfosResult<Vec<u32>>>()?;
+    if stops.len() < 2 {
+        return Err(invalid("a gradient needs at least two colors".to_string()));
+    }
+
+    let key = resolve_key(lfos, name);
+    let members = match lfos.groups.get(key) {
+        Some(members) => members.clone(),
+        None if is_key(lfos, key) => {
+            return Err(invalid(format!("'{}' is not a group, gradients need a group of keys", key)));
+        }
+        None => expand_keys(lfos, name)?,
+    };
+    if members.len() < stops.len() {
+        return Err(invalid(format!(
+            "'{}' has {} keys, which is fewer than the {} gradient colors",
+            key,
+            members.len(),
+            stops.len()
+        )));
+    }
+
+    for (i, member) in members.iter().enumerate() {
+        let t = i as f64 / (members.len() - 1) as f64;
+        blend.insert(overrides, member, gradient(&stops, t, space));
+    }
+
+    Ok(())
+}
+
+fn apply_color(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    name: &str,
+    color: &str,
+    blend: Blend,
+    space: ColorSpace,
+) -> LfosResult<()> {
+    match color.strip_prefix("grad:") {
+        Some(stops) => apply_gradient(lfos, overrides, name, color, stops, blend, space),
+        None => apply_override(lfos, overrides, name, parse_color(color)?, blend),
+    }
+}
+
+fn read_profile(path: &str) -> LfosResult<String> {
+    if path == "-" {
+        return Ok(std::io::read_to_string(std::io::stdin())?);
+    }
+
+    std::fs::read_to_string(path).map_err(|source| LfosError::ProfileRead {
+        path: path.to_string(),
+        source,
+    })
+}
+
+fn apply_profile(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    path: &str,
+    blend: Blend,
+    space: ColorSpace,
+) -> LfosResult<Vec<Blink>> {
+    let name = if path == "-" { "<stdin>" } else { path };
+    apply_profile_text(lfos, overrides, name, &read_profile(path)?, blend, space)
+}
+
+fn apply_profile_text(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    name: &str,
+    text: &str,
+    blend: Blend,
+    space: ColorSpace,
+) -> LfosResult<Vec<Blink>> {
+    let table: toml::Table = text
+        .parse()
+        .map_err(|err: toml::de::Error| LfosError::ProfileSyntax {
+            name: name.to_string(),
+            message: err.to_string(),
+        })?;
+
+    let mut blinks = Vec::new();
+    for (key, value) in table {
+        let applied = match &value {
+            toml::Value::String(color) => apply_color(lfos, overrides, &key, color, blend, space),
+            // Blinking keys are set to their on color too, for commands that only use steady colors
+            toml::Value::Table(settings) => parse_blink(lfos, &key, settings).and_then(|blink| {
+                apply_override(lfos, overrides, &key, blink.on, blend)?;
+                blinks.push(blink);
+                Ok(())
+            }),
+            _ => Err(LfosError::InvalidColor {
+                color: value.to_string(),
+                reason: format!("the color for '{}' must be a string like \"ff0000\"", key),
+            }),
+        };
+        applied.map_err(|source| LfosError::InProfile {
+                name: name.to_string(),
+                source: Box::new(source),
+            })?;
+    }
+
+    Ok(blinks)
+}
+
+fn parse_blink(lfos: &LFOS, key: &str, settings: &toml::Table) -> LfosResult<Blink> {
+    let color = |name: &str| -> LfosResult<Option<u32>> {
+        match settings.get(name) {
+            Some(toml::Value::String(color)) => Ok(Some(parse_color(color)?)),
+            Some(value) => Err(LfosError::InvalidColor {
+                color: value.to_string(),
+                reason: format!("the {} color for '{}' must be a string like \"ff0000\"", name, key),
+            }),
+            None => Ok(None),
+        }
+    };
+    let invalid = |name: &str, value: &toml::Value, expected: &str| LfosError::InvalidValue {
+        option: format!("{}.{}", key, name),
+        value: value.to_string(),
+        expected: expected.to_string(),
+    };
+
+    if let Some((name, value)) = settings.iter().find(|(name, _)| !["on", "off", "period_ms"].contains(&name.as_str())) {
+        return Err(invalid(name, value, "on, off or period_ms"));
+    }
+    let on = color("on")?.ok_or_else(|| LfosError::MissingValue {
+        option: format!("blinking key '{}'", key),
+        expected: "an on color".to_string(),
+    })?;
+    let period = match settings.get("period_ms") {
+        Some(value) => match value.as_integer().filter(|ms| *ms > 0) {
+            Some(ms) => Duration::from_millis(ms as u64),
+            None => return Err(invalid("period_ms", value, "a number of milliseconds above 0")),
+        },
+        None => Duration::from_secs(1),
+    };
+
+    Ok(Blink {
+        keys: expand_keys(lfos, key)?,
+        on,
+        off: color("off")?.unwrap_or(0),
+        period,
+    })
+}
+
+fn next_value<'a>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    option: &str,
+    expected: &str,
+) -> LfosResult<&'a String> {
+    iter.next().ok_or_else(|| LfosError::MissingValue {
+        option: option.to_string(),
+        expected: expected.to_string(),
+    })
+}
+
+fn try_parse_cmd(
+    lfos: &LFOS,
+    args: &[String],
+    config: &Config,
+) -> LfosResult<Command> {
+    let mut overrides = HashMap::<String, u32>::new();
+    let mut delay = Duration::ZERO;
+    let mut force_detach = false;
+    let mut reattach = true;
+    let mut persist = true;
+    let mut channels = ChannelOrder::Rgb;
+    let mut order = ApplyOrder::Forward;
+    let mut profiles = Vec::new();
+    let mut keymap = None;
+    let mut bound_color = None;
+    let mut unbound_color = None;
+    let mut pipe = None;
+    let mut broker = None;
+    let mut topic = None;
+    let mut hold = false;
+    let mut looped = false;
+    let mut update = false;
+    let mut dry_run = false;
+    let mut start_black = false;
+    let mut only = None;
+    let mut since_state = false;
+    let mut confirm = false;
+    let mut show_overrides = false;
+    let mut brightness = None;
+    let mut calibration = None;
+    let mut duration = Duration::from_millis(400);
+    let mut hold_time = Duration::from_millis(2000);
+    let mut fade_time = Duration::from_millis(1000);
+    let mut force = false;
+    let mut blend = Blend::Replace;
+    let mut interpolation = ColorSpace::Rgb;
+    let mut format = OutputFormat::Text;
+    let mut reset = None;
+    let mut capture = None;
+    let mut names = false;
+    let mut continue_on_error = false;
+    let mut stdin_json = false;
+    let mut power_budget = None;
+    let mut fps = 30;
+    let mut merge = false;
+    let mut table_hex = false;
+    let mut debug = false;
+    let mut transfer = TransferType::Interrupt;
+    let mut latency = Duration::ZERO;
+    let mut only_channel = None;
+    let mut alt_setting = None;
+    let mut output_file = None;
+    let mut max_packets = PACKET_COUNT;
+    let mut init = INIT_PACKET.clone();
+    let mut retry_open = 0;
+    let mut speed = None;
+    let mut density = None;
+    let mut seed = None;
+    let mut low = None;
+    let mut high = None;
+    let mut offset = None;
+    let mut key_size = 48;
+    let mut gap = 4;
+    let mut from_screen = false;
+    let mut interval = None;
+    let mut pairs = Vec::<&String>::new();
+
+    show_help_or_version(lfos, args);
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--delay-ms" => delay = parse_millis(arg, next_value(&mut iter, arg, "a number of milliseconds")?)?,
+            "--force-detach" => force_detach = true,
+            "--no-reattach" => reattach = false,
+            "--persist" => persist = true,
+            "--no-persist" => persist = false,
+            "--channel-order" => {
+                let value = next_value(&mut iter, arg, "rgb, bgr or grb")?;
+                channels = ChannelOrder::parse(value).ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "rgb, bgr or grb".to_string(),
+                })?;
+            }
+            "--apply-order" => {
+                let value = next_value(&mut iter, arg, "forward or reverse")?;
+                order = ApplyOrder::parse(value).ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "forward or reverse".to_string(),
+                })?;
+            }
+            "--dry-run" => dry_run = true,
+            "--start-black" => start_black = true,
+            "--only" => only = Some(next_value(&mut iter, arg, "a comma separated list of keys or groups")?),
+            "--since-state" => since_state = true,
+            "--confirm" => confirm = true,
+            "--show-overrides" => show_overrides = true,
+            "--debug" => debug = true,
+            "--retry-open" => {
+                let value = next_value(&mut iter, arg, "a number of retries")?;
+                retry_open = value.parse().ok().filter(|retries| *retries <= 20).ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "a number of retries from 0 to 20".to_string(),
+                })?;
+            }
+            "--init-hex" => {
+                let value = next_value(&mut iter, arg, "a packet as hex")?;
+                // Short packets are padded with zeros, like HEADER0 itself
+                init = decode_hex(value).filter(|packet| (1..=PACKET_LEN).contains(&packet.len())).ok_or_else(|| {
+                    LfosError::InvalidValue {
+                        option: arg.clone(),
+                        value: value.clone(),
+                        expected: format!("a packet of 1 to {} bytes as hex, like 04000200fcea", PACKET_LEN),
+                    }
+                })?;
+                init.resize(PACKET_LEN, 0);
+            }
+            "--max-packets" => {
+                let value = next_value(&mut iter, arg, "a number of packets")?;
+                max_packets = value.parse().ok().filter(|max| *max >= 1).ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "a number of packets above 0".to_string(),
+                })?;
+            }
+            "--simulate-latency" => latency = parse_millis(arg, next_value(&mut iter, arg, "a number of milliseconds")?)?,
+            "--transfer" => {
+                let value = next_value(&mut iter, arg, "interrupt or bulk")?;
+                transfer = match value.as_str() {
+                    "interrupt" => TransferType::Interrupt,
+                    "bulk" => TransferType::Bulk,
+                    _ => {
+                        return Err(LfosError::InvalidValue {
+                            option: arg.clone(),
+                            value: value.clone(),
+                            expected: "interrupt or bulk".to_string(),
+                        })
+                    }
+                };
+            }
+            "--only-channel" => {
+                let value = next_value(&mut iter, arg, "red, green or blue")?;
+                only_channel = Some(match value.as_str() {
+                    "red" => 16,
+                    "green" => 8,
+                    "blue" => 0,
+                    _ => {
+                        return Err(LfosError::InvalidValue {
+                            option: arg.clone(),
+                            value: value.clone(),
+                            expected: "red, green or blue".to_string(),
+                        })
+                    }
+                });
+            }
+            "--alt-setting" => {
+                let value = next_value(&mut iter, arg, "the number of an alternate setting")?;
+                alt_setting = Some(value.parse().map_err(|_| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "the number of an alternate setting from 0 to 255".to_string(),
+                })?);
+            }
+            "--print-table-hex" => {
+                table_hex = true;
+                dry_run = true;
+            }
+            "--output-file" => {
+                output_file = Some(PathBuf::from(next_value(&mut iter, arg, "a path to write the packets to")?));
+            }
+            "--names" => names = true,
+            "--continue-on-error" => continue_on_error = true,
+            "--stdin-json" => stdin_json = true,
+            "--fps" => {
+                let value = next_value(&mut iter, arg, "a number of frames per second")?;
+                fps = value.parse().ok().filter(|fps| (1..=1000).contains(fps)).ok_or_else(|| {
+                    LfosError::InvalidValue {
+                        option: arg.clone(),
+                        value: value.clone(),
+                        expected: "a number of frames per second from 1 to 1000".to_string(),
+                    }
+                })?;
+            }
+            "--speed" => speed = Some(parse_number(arg, next_value(&mut iter, arg, "a number")?)?),
+            "--density" => {
+                let value = next_value(&mut iter, arg, "a number from 0 to 1")?;
+                let number = parse_number(arg, value)?;
+                if !(0.0..=1.0).contains(&number) {
+                    return Err(LfosError::InvalidValue {
+                        option: arg.clone(),
+                        value: value.clone(),
+                        expected: "a number from 0 to 1".to_string(),
+                    });
+                }
+                density = Some(number);
+            }
+            "--seed" => {
+                let value = next_value(&mut iter, arg, "a number")?;
+                seed = Some(value.parse().map_err(|_| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "a whole number".to_string(),
+                })?);
+            }
+            "--low" => low = Some(parse_color(next_value(&mut iter, arg, "a color")?)?),
+            "--high" => high = Some(parse_color(next_value(&mut iter, arg, "a color")?)?),
+            "--offset" => offset = Some(parse_number(arg, next_value(&mut iter, arg, "a number of degrees")?)?),
+            "--merge" => merge = true,
+            "--force" => force = true,
+            "--from-screen" => from_screen = true,
+            "--interval-ms" => {
+                let value = next_value(&mut iter, arg, "a number of milliseconds")?;
+                interval = Some(parse_millis(arg, value)?.max(Duration::from_millis(1)));
+            }
+            "--duration-ms" => duration = parse_millis(arg, next_value(&mut iter, arg, "a number of milliseconds")?)?,
+            "--hold-ms" => hold_time = parse_millis(arg, next_value(&mut iter, arg, "a number of milliseconds")?)?,
+            "--fade-ms" => fade_time = parse_millis(arg, next_value(&mut iter, arg, "a number of milliseconds")?)?,
+            "--calibration" => {
+                calibration = Some(next_value(&mut iter, arg, "the path of a calibration file")?.clone());
+            }
+            "--brightness" => {
+                let value = next_value(&mut iter, arg, "a percentage")?;
+                let percent = value.parse().ok().filter(|percent| *percent <= 100);
+                brightness = Some(percent.ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "a percentage from 0 to 100".to_string(),
+                })?);
+            }
+            "--eco" => {
+                power_budget.get_or_insert(DEFAULT_POWER_BUDGET);
+            }
+            "--power-budget" => {
+                let value = next_value(&mut iter, arg, "a percentage of full white")?;
+                let budget = value.parse().ok().filter(|budget| (1..=100).contains(budget));
+                power_budget = Some(budget.ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "a percentage from 1 to 100".to_string(),
+                })?);
+            }
+            "--capture" => {
+                capture = Some(PathBuf::from(next_value(&mut iter, arg, "a path for the capture log")?));
+            }
+            "--reset-on-exit" => {
+                let value = next_value(&mut iter, arg, "a color, or off")?;
+                reset = Some(if value == "off" { 0 } else { parse_color(value)? });
+            }
+            "--format" => {
+                let value = next_value(&mut iter, arg, "text or json")?;
+                format = OutputFormat::parse(value).ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "text or json".to_string(),
+                })?;
+            }
+            "--interpolate" => {
+                let value = next_value(&mut iter, arg, "rgb or oklab")?;
+                interpolation = ColorSpace::parse(value).ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "rgb or oklab".to_string(),
+                })?;
+            }
+            "--blend" => {
+                let value = next_value(&mut iter, arg, "replace, add, max or average")?;
+                blend = Blend::parse(value).ok_or_else(|| LfosError::InvalidValue {
+                    option: arg.clone(),
+                    value: value.clone(),
+                    expected: "replace, add, max or average".to_string(),
+                })?;
+            }
+            "--profile" => {
+                profiles.push(ColorFile::Toml(next_value(&mut iter, arg, "a path, or - to read from stdin")?));
+            }
+            "--highlight-keymap" => {
+                keymap = Some(next_value(&mut iter, arg, "a path to a list of keys, or - to read from stdin")?);
+            }
+            "--bound-color" => bound_color = Some(parse_color(next_value(&mut iter, arg, "a color")?)?),
+            "--unbound-color" => unbound_color = Some(parse_color(next_value(&mut iter, arg, "a color")?)?),
+            "--csv" => {
+                profiles.push(ColorFile::Csv(next_value(&mut iter, arg, "a path, or - to read from stdin")?));
+            }
+            "--hold" => hold = true,
+            "--loop" => looped = true,
+            "--update" => update = true,
+            "--json-errors" => (),
+            "--pipe" => pipe = Some(next_value(&mut iter, arg, "a path for the named pipe")?),
+            "--broker" => broker = Some(next_value(&mut iter, arg, "the URL of an MQTT broker")?),
+            "--topic" => topic = Some(next_value(&mut iter, arg, "a topic prefix")?),
+            "--key-size" => key_size = parse_pixels(arg, next_value(&mut iter, arg, "a number of pixels")?, 1)?,
+            "--gap" => gap = parse_pixels(arg, next_value(&mut iter, arg, "a number of pixels")?, 0)?,
+            _ if arg.starts_with("--") => return Err(LfosError::UnknownOption(arg.clone())),
+            _ => pairs.push(arg),
+        }
+    }
+
+    if !latency.is_zero() && !dry_run {
+        return Err(LfosError::MissingValue {
+            option: "--simulate-latency".to_string(),
+            expected: "--dry-run or --print-table-hex to be given too".to_string(),
+        });
+    }
+
+    match keymap {
+        // The keymap lays down the base colors, profiles and keys given on the command line go on top
+        Some(path) => profiles.insert(0, ColorFile::Keymap {
+            path,
+            bound: bound_color.unwrap_or(0xffffff),
+            unbound: unbound_color.unwrap_or(0x202020),
+        }),
+        None if bound_color.is_some() || unbound_color.is_some() => {
+            return Err(LfosError::MissingValue {
+                option: "--bound-color and --unbound-color".to_string(),
+                expected: "--highlight-keymap to be given too".to_string(),
+            })
+        }
+        None => (),
+    }
+
+    let unshared = [
+        ("--channel-order", channels != ChannelOrder::Rgb),
+        ("--apply-order", order != ApplyOrder::Forward),
+        ("--brightness", brightness.is_some()),
+        ("--calibration", calibration.is_some()),
+        ("--eco", power_budget.is_some()),
+        ("--start-black", start_black),
+        ("--delay-ms", !delay.is_zero()),
+        ("--transfer", transfer != TransferType::Interrupt),
+        ("--alt-setting", alt_setting.is_some()),
+        ("--init-hex", init != *INIT_PACKET),
+        ("--max-packets", max_packets != PACKET_COUNT),
+        ("--only-channel", only_channel.is_some()),
+        ("--capture", capture.is_some()),
+        ("--force-detach", force_detach),
+        ("--no-reattach", !reattach),
+    ]
+    .into_iter()
+    .filter(|(_, given)| *given)
+    .map(|(option, _)| option)
+    .collect();
+
+    // The command line wins over the config file, which wins over full brightness and no calibration
+    let brightness = brightness.or(config.brightness).unwrap_or(100);
+    let calibration = match calibration.or_else(|| config.calibration.clone()) {
+        Some(path) => Some(calibration::load(lfos, &path)?),
+        None => None,
+    };
+
+    // "comma" names the , key, since the list itself is split on commas
+    let only = match only {
+        Some(names) => {
+            let names: Vec<String> = names.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect();
+            Some(collect_keys(lfos, "--only", &names.iter().collect::<Vec<_>>())?)
+        }
+        None => None,
+    };
+
+    // Developer options stay hidden unless --debug is given too
+    if only_channel.is_some() && !debug {
+        return Err(LfosError::UnknownOption("--only-channel".to_string()));
+    }
+
+    let mut action = match pairs.first().map(|arg| arg.as_str()) {
+        Some("selftest") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::SelfTest
+        }
+        Some("doctor") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Doctor
+        }
+        Some("tui") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Tui
+        }
+        Some("effect") if pairs.get(1).is_some_and(|name| *name == "audio") => {
+            let keys = match pairs.len() {
+                2 => expand_keys(lfos, "all")?,
+                _ => collect_keys(lfos, "effect audio", &pairs[2..])?,
+            };
+            Action::Audio {
+                keys,
+                low: low.unwrap_or(0x000000),
+                high: high.unwrap_or(0xffffff),
+            }
+        }
+        Some("effect") => {
+            let (effect, used) = match pairs.get(1).map(|name| name.as_str()) {
+                Some("cycle") => (
+                    Effect::Cycle {
+                        speed: speed.unwrap_or(0.2),
+                        offset: offset.unwrap_or(10.0),
+                        keys: match pairs.len() {
+                            2 => None,
+                            _ => Some(collect_keys(lfos, "effect cycle", &pairs[2..])?),
+                        },
+                    },
+                    pairs.len(),
+                ),
+                Some("matrix") => {
+                    let color = pairs.get(2).ok_or_else(|| LfosError::MissingValue {
+                        option: "effect matrix".to_string(),
+                        expected: "a color".to_string(),
+                    })?;
+                    (
+                        Effect::Matrix {
+                            color: parse_color(color)?,
+                            speed: speed.unwrap_or(4.0),
+                            density: density.unwrap_or(0.5),
+                        },
+                        3,
+                    )
+                }
+                Some("palette-breathe") => {
+                    if pairs.len() < 4 {
+                        return Err(LfosError::MissingValue {
+                            option: "effect palette-breathe".to_string(),
+                            expected: "two or more colors".to_string(),
+                        });
+                    }
+                    if (hold_time + fade_time).is_zero() {
+                        return Err(LfosError::InvalidValue {
+                            option: "--hold-ms".to_string(),
+                            value: "0".to_string(),
+                            expected: "more than 0 when --fade-ms is 0 too".to_string(),
+                        });
+                    }
+                    let colors = pairs[2..].iter().map(|color| parse_color(color)).collect::<LfosResult<_>>()?;
+                    (
+                        Effect::PaletteBreathe {
+                            colors,
+                            hold: hold_time,
+                            fade: fade_time,
+                            space: interpolation,
+                        },
+                        pairs.len(),
+                    )
+                }
+                Some("wander") => {
+                    if pairs.len() < 4 {
+                        return Err(LfosError::MissingValue {
+                            option: "effect wander".to_string(),
+                            expected: "two or more colors".to_string(),
+                        });
+                    }
+                    let colors = pairs[2..].iter().map(|color| parse_color(color)).collect::<LfosResult<_>>()?;
+                    // Without --seed every run wanders its own way
+                    let seed = seed.unwrap_or_else(|| {
+                        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos() as u64
+                    });
+                    (
+                        Effect::Wander {
+                            colors,
+                            speed: speed.unwrap_or(0.1),
+                            seed,
+                            space: interpolation,
+                        },
+                        pairs.len(),
+                    )
+                }
+                Some(name) => {
+                    return Err(LfosError::InvalidValue {
+                        option: "effect".to_string(),
+                        value: name.to_string(),
+                        expected: "cycle, matrix, palette-breathe, wander or audio".to_string(),
+                    })
+                }
+                None => {
+                    return Err(LfosError::MissingValue {
+                        option: "effect".to_string(),
+                        expected: "the name of an effect: cycle, matrix, palette-breathe, wander or audio".to_string(),
+                    })
+                }
+            };
+            if pairs.len() > used {
+                return Err(LfosError::UnknownOption(pairs[used].clone()));
+            }
+            Action::Effect(effect)
+        }
+        Some("info") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Info
+        }
+        Some("colors") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Colors
+        }
+        Some("layout") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Layout
+        }
+        Some("bench-apply") => {
+            let runs = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "bench-apply".to_string(),
+                expected: "how many times to apply the colors".to_string(),
+            })?;
+            let runs = runs.parse().ok().filter(|runs| *runs > 0).ok_or_else(|| LfosError::InvalidValue {
+                option: "bench-apply".to_string(),
+                value: runs.to_string(),
+                expected: "a number of applies above 0".to_string(),
+            })?;
+            parse_colors(lfos, &mut overrides, &profiles, pairs[2..].to_vec(), blend, interpolation, continue_on_error, stdin_json)?;
+            Action::BenchApply(runs)
+        }
+        Some("restore") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Restore
+        }
+        Some("hue-shift") => {
+            let degrees = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "hue-shift".to_string(),
+                expected: "a number of degrees".to_string(),
+            })?;
+            if pairs.len() > 2 {
+                return Err(LfosError::UnknownOption(pairs[2].clone()));
+            }
+            let degrees = degrees.parse().map_err(|_| LfosError::InvalidValue {
+                option: "hue-shift".to_string(),
+                value: degrees.to_string(),
+                expected: "a number of degrees".to_string(),
+            })?;
+            for file in &profiles {
+                file.apply(lfos, &mut overrides, blend, interpolation, continue_on_error)?;
+            }
+            Action::HueShift(degrees)
+        }
+        Some("serve") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Serve(pipe.map_or_else(default_pipe_path, PathBuf::from))
+        }
+        Some("mqtt") => {
+            if pairs.len() > 1 {
+                return Err(LfosError::UnknownOption(pairs[1].clone()));
+            }
+            Action::Mqtt {
+                broker: broker
+                    .ok_or_else(|| LfosError::MissingValue {
+                        option: "mqtt".to_string(),
+                        expected: "--broker with the URL of an MQTT broker".to_string(),
+                    })?
+                    .to_string(),
+                topic: topic.map_or(env!("CARGO_PKG_NAME"), String::as_str).to_string(),
+            }
+        }
+        Some("svg") => {
+            let path = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "svg".to_string(),
+                expected: "the path of the SVG file, or - to print it".to_string(),
+            })?;
+            if pairs.len() > 2 || !profiles.is_empty() || stdin_json {
+                parse_colors(lfos, &mut overrides, &profiles, pairs[2..].to_vec(), blend, interpolation, continue_on_error, stdin_json)?;
+            }
+            Action::Svg {
+                path: path.to_string(),
+                key_size,
+                gap,
+            }
+        }
+        Some("export") => {
+            // Without any colors, export the saved ones instead of the default profile
+            if pairs.len() > 1 || !profiles.is_empty() || stdin_json {
+                parse_colors(lfos, &mut overrides, &profiles, pairs[1..].to_vec(), blend, interpolation, continue_on_error, stdin_json)?;
+            }
+            Action::Export
+        }
+        Some("share") => {
+            // Like export, without any colors the saved ones are shared
+            if pairs.len() > 1 || !profiles.is_empty() || stdin_json {
+                parse_colors(lfos, &mut overrides, &profiles, pairs[1..].to_vec(), blend, interpolation, continue_on_error, stdin_json)?;
+            }
+            Action::Share
+        }
+        Some("replay") => {
+            let path = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "replay".to_string(),
+                expected: "the path of a log written by --capture, or - to read from stdin".to_string(),
+            })?;
+            if pairs.len() > 2 {
+                return Err(LfosError::UnknownOption(pairs[2].clone()));
+            }
+            Action::Replay {
+                path: path.to_string(),
+                looped,
+            }
+        }
+        // Left out of --help, it's for checking changes to the table building against known good tables
+        Some("verify-golden") => {
+            let path = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "verify-golden".to_string(),
+                expected: "the path of a golden file".to_string(),
+            })?;
+            parse_colors(lfos, &mut overrides, &profiles, pairs[2..].to_vec(), blend, interpolation, continue_on_error, stdin_json)?;
+            Action::VerifyGolden {
+                path: path.to_string(),
+                update,
+            }
+        }
+        Some("heatmap") => {
+            let path = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "heatmap".to_string(),
+                expected: "the path of a file of key press counts, or - to read from stdin".to_string(),
+            })?;
+            if pairs.len() > 2 {
+                return Err(LfosError::UnknownOption(pairs[2].clone()));
+            }
+            let stops = match (low, high) {
+                (None, None) => heatmap::COLD_TO_HOT.to_vec(),
+                (low, high) => vec![
+                    low.unwrap_or(heatmap::COLD_TO_HOT[0]),
+                    high.unwrap_or(heatmap::COLD_TO_HOT[heatmap::COLD_TO_HOT.len() - 1]),
+                ],
+            };
+            overrides = heatmap::load(lfos, path, &stops, interpolation)?;
+            Action::Apply
+        }
+        Some("apply-share") => {
+            let blob = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "apply-share".to_string(),
+                expected: "a blob printed by share".to_string(),
+            })?;
+            if pairs.len() > 2 {
+                return Err(LfosError::UnknownOption(pairs[2].clone()));
+            }
+            overrides = share::decode(lfos, blob)?;
+            Action::Apply
+        }
+        Some("validate") => {
+            let path = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "validate".to_string(),
+                expected: "the path of a profile, or - to read from stdin".to_string(),
+            })?;
+            if pairs.len() > 2 {
+                return Err(LfosError::UnknownOption(pairs[2].clone()));
+            }
+            Action::Validate(path.to_string())
+        }
+        Some("accent") => {
+            if !from_screen {
+                return Err(LfosError::MissingValue {
+                    option: "accent".to_string(),
+                    expected: "--from-screen, where to take the color from".to_string(),
+                });
+            }
+            let keys = collect_keys(lfos, "accent", &pairs[1..])?;
+            Action::Accent { keys, interval }
+        }
+        Some("new-profile") => {
+            let path = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "new-profile".to_string(),
+                expected: "the path of the new profile, or - to print it".to_string(),
+            })?;
+            if pairs.len() > 2 {
+                return Err(LfosError::UnknownOption(pairs[2].clone()));
+            }
+            Action::NewProfile {
+                path: path.to_string(),
+                force,
+            }
+        }
+        Some("pulse") => {
+            let (key, color) = match pairs[1..] {
+                [key, color] => (key, color),
+                [_, _, extra, ..] => return Err(LfosError::UnknownOption(extra.clone())),
+                _ => {
+                    return Err(LfosError::MissingValue {
+                        option: "pulse".to_string(),
+                        expected: "a key or group and a color".to_string(),
+                    })
+                }
+            };
+            Action::Pulse {
+                keys: expand_keys(lfos, key)?,
+                color: parse_color(color)?,
+                duration,
+            }
+        }
+        Some("index") => {
+            if pairs.len() < 2 {
+                return Err(LfosError::MissingValue {
+                    option: "index".to_string(),
+                    expected: "one or more keys".to_string(),
+                });
+            }
+            let mut keys = Vec::new();
+            for name in &pairs[1..] {
+                let key = resolve_key(lfos, name);
+                if lfos.groups.contains_key(key) || key == "all" {
+                    return Err(LfosError::InvalidValue {
+                        option: "index".to_string(),
+                        value: name.to_string(),
+                        expected: "a key, not a group".to_string(),
+                    });
+                }
+                if !lfos.keys.contains(&key) {
+                    return Err(unknown_key(lfos, name));
+                }
+                keys.push(key.to_string());
+            }
+            Action::Index(keys)
+        }
+        Some("copy") => {
+            let name = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "copy".to_string(),
+                expected: "a key to copy the color of, then the keys to copy it to".to_string(),
+            })?;
+            let source = match expand_keys(lfos, name)?.as_slice() {
+                [key] => key.clone(),
+                _ => {
+                    return Err(LfosError::InvalidValue {
+                        option: "copy".to_string(),
+                        value: name.to_string(),
+                        expected: "a single key to copy the color of, not a group".to_string(),
+                    })
+                }
+            };
+            Action::Copy {
+                source,
+                keys: collect_keys(lfos, "copy", &pairs[2..])?,
+            }
+        }
+        Some("pick") => Action::Pick(collect_keys(lfos, "pick", &pairs[1..])?),
+        Some("toggle") => Action::Toggle(collect_keys(lfos, "toggle", &pairs[1..])?),
+        Some("clear") => {
+            let keys = collect_keys(lfos, "clear", &pairs[1..])?;
+            if merge {
+                Action::Clear(keys)
+            } else {
+                for key in keys {
+                    overrides.insert(key, 0);
+                }
+                Action::Apply
+            }
+        }
+        Some("theme") => {
+            let name = pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+                option: "theme".to_string(),
+                expected: format!("daily or the name of a theme: {}", theme::names().join(", ")),
+            })?;
+            let theme = match name.as_str() {
+                "daily" => {
+                    let theme = theme::daily(theme::today(), seed.unwrap_or(0));
+                    eprintln!("theme of the day: {}", theme.name);
+                    theme
+                }
+                name => theme::find(name).ok_or_else(|| LfosError::InvalidValue {
+                    option: "theme".to_string(),
+                    value: name.to_string(),
+                    expected: format!("daily or one of {}", theme::names().join(", ")),
+                })?,
+            };
+            for (key, color) in theme.colors {
+                apply_color(lfos, &mut overrides, key, color, blend, interpolation)?;
+            }
+            // Profiles and keys given on the command line go on top, like with solid
+            let mut blinks = Vec::new();
+            if pairs.len() > 2 || !profiles.is_empty() || stdin_json {
+                blinks = parse_colors(lfos, &mut overrides, &profiles, pairs[2..].to_vec(), blend, interpolation, continue_on_error, stdin_json)?;
+            }
+            apply_action(&overrides, blinks)
+        }
+        _ => {
+            let blinks = parse_colors(lfos, &mut overrides, &profiles, pairs, blend, interpolation, continue_on_error, stdin_json)?;
+            apply_config_colors(lfos, &mut overrides, &config.colors)?;
+            apply_action(&overrides, blinks)
+        }
+    };
+
+    // Goes last, over whatever all, groups and profiles gave the keys
+    if let Some(keys) = &only {
+        overrides = only_keys(&overrides, keys);
+        if let Action::Effect(Effect::Blink { base, blinks }) = &mut action {
+            for blink in blinks.iter_mut() {
+                blink.keys.retain(|key| keys.contains(key));
+            }
+            blinks.retain(|blink| !blink.keys.is_empty());
+            *base = only_keys(base, keys);
+        }
+        if matches!(&action, Action::Effect(Effect::Blink { blinks, .. }) if blinks.is_empty()) {
+            action = Action::Apply;
+        }
+    }
+
+    Ok(Command {
+        action,
+        overrides,
+        delay,
+        force_detach,
+        reattach,
+        persist,
+        channels,
+        order,
+        blend,
+        interpolation,
+        format,
+        reset,
+        capture,
+        names,
+        power_budget,
+        table_hex,
+        latency,
+        transfer,
+        only_channel,
+        alt_setting,
+        output_file,
+        max_packets,
+        init,
+        retry_open,
+        brightness,
+        calibration,
+        fps,
+        hold,
+        dry_run,
+        start_black,
+        since_state,
+        confirm,
+        show_overrides,
+        unshared,
+    })
+}
+
+// Colors from the config file sit below everything else: they only fill in keys that weren't given a color,
+// and only while "all" wasn't set either, since that already says what every other key should be
+fn apply_config_colors(lfos: &LFOS, overrides: &mut HashMap<String, u32>, colors: &[(String, String)]) -> LfosResult<()> {
+    if overrides.contains_key("all") {
+        return Ok(());
+    }
+    let mut defaults = HashMap::new();
+    for (name, color) in colors {
+        apply_color(lfos, &mut defaults, name, color, Blend::Replace, ColorSpace::Rgb).map_err(|err| LfosError::Config {
+            path: config::config_path().map(|path| path.display().to_string()).unwrap_or_default(),
+            reason: format!("[colors] {}", err),
+        })?;
+    }
+    for (key, color) in defaults {
+        overrides.entry(key).or_insert(color);
+    }
+    Ok(())
+}
+
+// Applying colors turns into an effect as soon as a profile has blinking keys
+// The given keys keep the color they resolved to, and every other key is off
+fn only_keys(overrides: &HashMap<String, u32>, keys: &[String]) -> HashMap<String, u32> {
+    let all = overrides.get("all").copied().unwrap_or(0xffffff);
+    let mut only: HashMap<String, u32> = keys.iter().map(|key| (key.clone(), *overrides.get(key).unwrap_or(&all))).collect();
+    only.insert("all".to_string(), 0);
+    only
+}
+
+fn apply_action(overrides: &HashMap<String, u32>, blinks: Vec<Blink>) -> Action {
+    if blinks.is_empty() {
+        Action::Apply
+    } else {
+        Action::Effect(Effect::Blink {
+            base: overrides.clone(),
+            blinks,
+        })
+    }
+}
+
+fn profile_line(text: &str, key: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| {
+            let name = line.split('=').next().unwrap_or_default();
+            line.contains('=') && name.trim().trim_matches(|c| c == '"' || c == '\'') == key
+        })
+        .map(|i| i + 1)
+}
+
+fn validate_profile(lfos: &LFOS, text: &str) -> Vec<Problem> {
+    let table = match text.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(err) => {
+            let line = err
+                .span()
+                .map(|span| text[..span.start].matches('\n').count() + 1);
+            return vec![Problem {
+                line,
+                key: None,
+                message: err.message().replace('\n', ", "),
+            }];
+        }
+    };
+
+    // Every entry is checked on its own, so one mistake doesn't hide the ones after it
+    let mut problems = Vec::new();
+    for (key, value) in table {
+        let checked = match &value {
+            toml::Value::String(color) => apply_color(lfos, &mut HashMap::new(), &key, color, Blend::Replace, ColorSpace::Rgb),
+            toml::Value::Table(settings) => parse_blink(lfos, &key, settings).map(|_| ()),
+            _ => Err(LfosError::InvalidColor {
+                color: value.to_string(),
+                reason: format!("the color for '{}' must be a string like \"ff0000\"", key),
+            }),
+        };
+        if let Err(err) = checked {
+            problems.push(Problem {
+                line: profile_line(text, &key),
+                message: err.to_string(),
+                key: Some(key),
+            });
+        }
+    }
+
+    problems
+}
+
+fn run_validate(lfos: &LFOS, command: &Command, path: &str) -> LfosResult<()> {
+    let name = if path == "-" { "<stdin>" } else { path };
+    let problems = validate_profile(lfos, &read_profile(path)?);
+    if !problems.is_empty() {
+        return Err(LfosError::InvalidProfile {
+            name: name.to_string(),
+            problems,
+        });
+    }
+
+    match command.format {
+        OutputFormat::Text => println!("profile '{}' is valid", name),
+        OutputFormat::Json => println!("{}", serde_json::json!({ "profile": name, "problems": [] })),
+    }
+    Ok(())
+}
+
+// Straight from the table parse_color reads, sorted by name so the output doesn't change with the table's order
+fn run_colors() {
+    let swatches = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+    let mut colors = color::NAMED_COLORS.to_vec();
+    colors.sort();
+    for (name, rgb) in colors {
+        match swatches {
+            true => {
+                let [_, r, g, b] = rgb.to_be_bytes();
+                println!("{:<8} {:06x} \x1b[48;2;{};{};{}m    \x1b[0m", name, rgb, r, g, b);
+            }
+            false => println!("{:<8} {:06x}", name, rgb),
+        }
+    }
+}
+
+// Bumped whenever a field of the layout output changes meaning or goes away
+const LAYOUT_VERSION: u32 = 1;
+
+// Keys drawn bigger than one grid cell, as (width, height) in cells. They grow right and down from their own cell,
+// into cells no other key uses
+const KEY_SIZES: &[(&str, (u8, u8))] = &[
+    ("rshift", (2, 1)),
+    ("space", (4, 1)),
+    ("enter", (1, 2)),
+    ("numpad+", (1, 2)),
+    ("numpadenter", (1, 2)),
+    ("numpad0", (2, 1)),
+];
+
+// Every slot of the key table in order, so its index is the one the packets use. Reserved slots have no position
+fn layout_keys(lfos: &LFOS) -> Vec<serde_json::Value> {
+    lfos.keys
+        .iter()
+        .enumerate()
+        .map(|(index, key)| match lfos.positions.get(key) {
+            Some((row, col)) => {
+                let (width, height) = KEY_SIZES.iter().find(|(name, _)| name == key).map_or((1, 1), |(_, size)| *size);
+                serde_json::json!({
+                    "index": index,
+                    "name": key,
+                    "row": row,
+                    "col": col,
+                    "width": width,
+                    "height": height,
+                    "reserved": false,
+                })
+            }
+            None => serde_json::json!({ "index": index, "name": key, "reserved": true }),
+        })
+        .collect()
+}
+
+fn run_layout(lfos: &LFOS, command: &Command) {
+    let max_row = lfos.positions.values().map(|(row, _)| *row).max().unwrap_or(0);
+    let max_col = lfos.positions.values().map(|(_, col)| *col).max().unwrap_or(0);
+    let leds = (0..LEDS_PER_LINE * 3).filter(|index| has_led(*index)).count();
+
+    match command.format {
+        OutputFormat::Text => {
+            println!("version: {}", LAYOUT_VERSION);
+            println!("max_row: {}", max_row);
+            println!("max_col: {}", max_col);
+            println!("leds: {}", leds);
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::json!({
+                "version": LAYOUT_VERSION,
+                "max_row": max_row,
+                "max_col": max_col,
+                "leds": leds,
+                "keys": layout_keys(lfos),
+            })
+        ),
+    }
+}
+
+fn parse_number(option: &str, value: &str) -> LfosResult<f64> {
+    value.parse().ok().filter(|number: &f64| number.is_finite()).ok_or_else(|| LfosError::InvalidValue {
+        option: option.to_string(),
+        value: value.to_string(),
+        expected: "a number".to_string(),
+    })
+}
+
+fn parse_millis(option: &str, value: &str) -> LfosResult<Duration> {
+    value.parse().map(Duration::from_millis).map_err(|_| LfosError::InvalidValue {
+        option: option.to_string(),
+        value: value.to_string(),
+        expected: "a whole number of milliseconds".to_string(),
+    })
+}
+
+fn parse_pixels(option: &str, value: &str, min: u32) -> LfosResult<u32> {
+    value.parse().ok().filter(|pixels| *pixels >= min).ok_or_else(|| LfosError::InvalidValue {
+        option: option.to_string(),
+        value: value.to_string(),
+        expected: format!("a number of pixels, at least {}", min),
+    })
+}
+
+// Commands that read a scheme use the colors they were given, or the saved ones
+fn given_or_saved_colors(lfos: &LFOS, command: &Command) -> LfosResult<BTreeMap<String, u32>> {
+    if command.overrides.is_empty() {
+        Ok(saved_state(command)?.colors)
+    } else {
+        Ok(resolve_colors(lfos, &command.overrides))
+    }
+}
+
+fn run_svg(lfos: &LFOS, command: &Command, path: &str, key_size: u32, gap: u32) -> LfosResult<()> {
+    let svg = svg::render(lfos, &given_or_saved_colors(lfos, command)?, key_size, gap);
+    if path == "-" {
+        print!("{}", svg);
+    } else {
+        std::fs::write(path, svg)?;
+    }
+    Ok(())
+}
+
+fn run_export(lfos: &LFOS, command: &Command) -> LfosResult<()> {
+    let colors = given_or_saved_colors(lfos, command)?;
+
+    for (key, color) in colors {
+        let entry = toml::Table::from_iter([(key, toml::Value::String(format!("{:06x}", color)))]);
+        let line = entry.to_string();
+        if command.names {
+            println!("{} # ~{}", line.trim_end(), nearest_color(color));
+        } else {
+            println!("{}", line.trim_end());
+        }
+    }
+    Ok(())
+}
+
+fn profile_entry(key: &str, color: &str) -> String {
+    let entry = toml::Table::from_iter([(key.to_string(), toml::Value::String(color.to_string()))]);
+    entry.to_string().trim_end().to_string()
+}
+
+fn profile_template(lfos: &LFOS) -> String {
+    let mut text = format!(
+        "# Profile for {0}, apply it with: {0} --profile <path>\n\
+         # Lines are applied from top to bottom, so later lines win over earlier ones.\n\
+         # Colors are hex like \"ff8000\" or names like \"orange\", uncomment a line to use it.\n\n{1}\n\n# Groups\n",
+        LFOS_NAME,
+        profile_entry("all", "ffffff")
+    );
+
+    let mut groups: Vec<_> = lfos.groups.keys().collect();
+    groups.sort();
+    for group in groups {
+        text.push_str(&format!("# {}\n", profile_entry(group, "ffffff")));
+    }
+
+    // Keys go row by row, the way they are on the keyboard
+    let mut keys: Vec<_> = lfos.keys.iter().filter(|key| **key != "????").collect();
+    keys.sort_by_key(|key| lfos.positions.get(**key));
+    text.push_str("\n# Keys\n");
+    for key in keys {
+        text.push_str(&format!("# {}\n", profile_entry(key, "ffffff")));
+    }
+
+    text
+}
+
+// Shows where a key's colors go on the wire, using the same layout as build_table
+fn run_index(lfos: &LFOS, keys: &[String]) {
+    for key in keys {
+        for (j, _) in lfos.keys.iter().enumerate().filter(|(_, name)| **name == key) {
+            let lit = if has_led(j) { "" } else { ", no LED" };
+            println!("{}: index {}{}", key, j, lit);
+            for (l, line) in LINES.iter().enumerate().filter(|(l, _)| l % 3 == j / LEDS_PER_LINE) {
+                let channel = match line.ofset {
+                    16 => "red",
+                    8 => "green",
+                    _ => "blue",
+                };
+                let byte = line.header.len() + j % LEDS_PER_LINE;
+                println!("\tpacket {}, byte {} ({})", l + 1, byte, channel);
+            }
+        }
+    }
+}
+
+fn run_new_profile(lfos: &LFOS, path: &str, force: bool) -> LfosResult<()> {
+    let template = profile_template(lfos);
+    if path == "-" {
+        print!("{}", template);
+        return Ok(());
+    }
+
+    if !force && Path::new(path).exists() {
+        return Err(LfosError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists, add --force to overwrite it", path),
+        )));
+    }
+    std::fs::write(path, template)?;
+    Ok(())
+}
+
+// Files of colors are applied in the order they were given, whatever their format
+#[derive(Clone, Copy)]
+enum ColorFile<'a> {
+    Toml(&'a str),
+    Csv(&'a str),
+    Keymap { path: &'a str, bound: u32, unbound: u32 },
+}
+
+impl ColorFile<'_> {
+    fn apply(
+        self,
+        lfos: &LFOS,
+        overrides: &mut HashMap<String, u32>,
+        blend: Blend,
+        space: ColorSpace,
+        continue_on_error: bool,
+    ) -> LfosResult<Vec<Blink>> {
+        match self {
+            ColorFile::Toml(path) => apply_profile(lfos, overrides, path, blend, space),
+            ColorFile::Csv(path) => apply_csv(lfos, overrides, path, blend, space, continue_on_error).map(|()| Vec::new()),
+            ColorFile::Keymap { path, bound, unbound } => {
+                apply_keymap(lfos, overrides, path, bound, unbound, continue_on_error).map(|()| Vec::new())
+            }
+        }
+    }
+}
+
+// A first row like "key,color" is the header of a spreadsheet export, not a key that doesn't exist
+fn is_csv_header(lfos: &LFOS, fields: &[&str]) -> bool {
+    match fields {
+        [key, color] => expand_keys(lfos, key).is_err() && parse_color(color).is_err(),
+        _ => false,
+    }
+}
+
+fn apply_csv(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    path: &str,
+    blend: Blend,
+    space: ColorSpace,
+    continue_on_error: bool,
+) -> LfosResult<()> {
+    let name = if path == "-" { "<stdin>" } else { path };
+    let text = read_profile(path)?;
+    let mut problems = Vec::new();
+    let mut first = true;
+
+    // Spreadsheets like to start their exports with a byte order mark
+    for (index, row) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        let fields: Vec<&str> = row.split(',').map(|field| field.trim().trim_matches('"')).collect();
+        if fields.iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        if std::mem::take(&mut first) && is_csv_header(lfos, &fields) {
+            continue;
+        }
+
+        let applied = match fields[..] {
+            [key, color] => apply_color(lfos, overrides, key, color, blend, space),
+            _ => Err(LfosError::InvalidValue {
+                option: "--csv".to_string(),
+                value: row.trim().to_string(),
+                expected: "a key and a color separated by a comma".to_string(),
+            }),
+        };
+        match applied {
+            Ok(()) => (),
+            Err(err) if continue_on_error => eprintln!("warning: skipping line {}: {}", index + 1, err),
+            Err(err) => problems.push(Problem {
+                line: Some(index + 1),
+                key: Some(fields[0].to_string()),
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(LfosError::InvalidProfile {
+            name: name.to_string(),
+            problems,
+        });
+    }
+    Ok(())
+}
+
+fn apply_keymap(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    path: &str,
+    bound: u32,
+    unbound: u32,
+    continue_on_error: bool,
+) -> LfosResult<()> {
+    let name = if path == "-" { "<stdin>" } else { path };
+    let text = read_profile(path)?;
+    let mut keys = Vec::new();
+    let mut problems = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_keys = line.split('#').next().unwrap_or_default();
+        for key in line_keys.split(|c: char| c == ',' || c.is_whitespace()).filter(|key| !key.is_empty()) {
+            match expand_keys(lfos, key) {
+                Ok(expanded) => keys.extend(expanded),
+                Err(err) if continue_on_error => eprintln!("warning: skipping line {}: {}", index + 1, err),
+                Err(err) => problems.push(Problem {
+                    line: Some(index + 1),
+                    key: Some(key.to_string()),
+                    message: err.to_string(),
+                }),
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(LfosError::InvalidProfile {
+            name: name.to_string(),
+            problems,
+        });
+    }
+
+    // Blending doesn't make sense here, the keymap decides every key on its own
+    overrides.insert("all".to_string(), unbound);
+    for key in keys {
+        overrides.insert(key, bound);
+    }
+    Ok(())
+}
+
+fn apply_json_text(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    name: &str,
+    text: &str,
+    blend: Blend,
+    space: ColorSpace,
+    continue_on_error: bool,
+) -> LfosResult<()> {
+    let map = match serde_json::from_str(text) {
+        Ok(serde_json::Value::Object(map)) => map,
+        Ok(_) => {
+            return Err(LfosError::ProfileSyntax {
+                name: name.to_string(),
+                message: "expected a JSON object of keys and colors".to_string(),
+            })
+        }
+        Err(err) => {
+            return Err(LfosError::ProfileSyntax {
+                name: name.to_string(),
+                message: err.to_string(),
+            })
+        }
+    };
+
+    for (key, value) in map {
+        let applied = match value.as_str() {
+            Some(color) => apply_color(lfos, overrides, &key, color, blend, space),
+            None => Err(LfosError::InvalidColor {
+                color: value.to_string(),
+                reason: format!("the color for '{}' must be a string like \"ff0000\"", key),
+            }),
+        };
+        match applied {
+            Ok(()) => (),
+            Err(err) if continue_on_error => eprintln!("warning: skipping '{}': {}", key, err),
+            Err(source) => {
+                return Err(LfosError::InProfile {
+                    name: name.to_string(),
+                    source: Box::new(source),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Finds the key that was left without a color: the first one followed by another key where a color should be, or
+// else the last one. Positions count from 1 and leave out "solid <color>"
+fn odd_args(lfos: &LFOS, solid: Option<&String>, pairs: &[&String]) -> LfosError {
+    let is_name = |arg: &str| parse_color(arg).is_err() && expand_keys(lfos, arg).is_ok();
+    let index = (0..pairs.len())
+        .step_by(2)
+        .find(|i| pairs.get(i + 1).is_some_and(|next| is_name(next)))
+        .unwrap_or(pairs.len() - 1);
+
+    let mut fixed = vec![LFOS_NAME.to_string()];
+    if let Some(color) = solid {
+        fixed.extend(["solid".to_string(), color.to_string()]);
+    }
+    for (i, arg) in pairs.iter().enumerate() {
+        fixed.push(arg.to_string());
+        if i == index {
+            fixed.push("<color>".to_string());
+        }
+    }
+    LfosError::OddArgs {
+        key: pairs[index].to_string(),
+        position: index + 1,
+        fixed: fixed.join(" "),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_colors(
+    lfos: &LFOS,
+    overrides: &mut HashMap<String, u32>,
+    profiles: &[ColorFile],
+    mut pairs: Vec<&String>,
+    blend: Blend,
+    space: ColorSpace,
+    continue_on_error: bool,
+    stdin_json: bool,
+) -> LfosResult<Vec<Blink>> {
+    let mut solid = None;
+    if pairs.first().is_some_and(|arg| *arg == "solid") {
+        solid = Some(*pairs.get(1).ok_or_else(|| LfosError::MissingValue {
+            option: "solid".to_string(),
+            expected: "a color".to_string(),
+        })?);
+        pairs.drain(..2);
+    }
+
+    if !pairs.len().is_multiple_of(2) {
+        if !continue_on_error {
+            return Err(odd_args(lfos, solid, &pairs));
+        }
+        if let Some(key) = pairs.pop() {
+            eprintln!("warning: ignoring '{}', it was not given a color", key);
+        }
+    }
+
+    // Later profiles win over earlier ones, and the keys given on the command line over all of them
+    let mut blinks = Vec::new();
+    for file in profiles {
+        blinks.extend(file.apply(lfos, overrides, blend, space, continue_on_error)?);
+    }
+    if stdin_json {
+        let text = std::io::read_to_string(std::io::stdin())?;
+        apply_json_text(lfos, overrides, "<stdin>", &text, blend, space, continue_on_error)?;
+    } else if profiles.is_empty() && pairs.is_empty() && solid.is_none() {
+        if let Some(path) = std::env::var_os(LFOS_DEFAULT_PROFILE).filter(|path| !path.is_empty()) {
+            blinks = apply_profile(lfos, overrides, &path.to_string_lossy(), blend, space).map_err(|source| {
+                LfosError::DefaultProfile {
+                    variable: LFOS_DEFAULT_PROFILE.to_string(),
+                    source: Box::new(source),
+                }
+            })?;
+        }
+    }
+
+    if let Some(color) = solid {
+        apply_override(lfos, overrides, "all", parse_color(color)?, blend)?;
+        blinks.clear();
+    }
+
+    let mut first_error = None;
+    let mut applied = 0;
+    for pair in pairs.chunks(2) {
+        match apply_color(lfos, overrides, pair[0], pair[1], blend, space) {
+            Ok(()) => {
+                // Keys given on the command line win over a blinking profile too
+                let keys = expand_keys(lfos, pair[0]).unwrap_or_default();
+                for blink in &mut blinks {
+                    blink.keys.retain(|key| !keys.contains(key));
+                }
+                applied += 1;
+            }
+            Err(err) if continue_on_error => {
+                eprintln!("warning: skipping '{} {}': {}", pair[0], pair[1], err);
+                first_error.get_or_insert(err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    // Applying nothing at all would turn the whole keyboard white, which is never what was meant
+    match first_error {
+        Some(err) if applied == 0 && solid.is_none() && profiles.is_empty() && !stdin_json => Err(err),
+        _ => Ok(blinks.into_iter().filter(|blink| !blink.keys.is_empty()).collect()),
+    }
+}
+
+pub fn build_table(
+    keys: &[&str],
+    overrides: &HashMap<String, u32>,
+    default: u32,
+    channels: ChannelOrder,
+) -> Vec<Vec<u8>> {
+    let all = *overrides.get("all").unwrap_or(&default);
+    let mut result = Vec::with_capacity(PACKET_COUNT);
+    result.push(INIT_PACKET.clone());
+
+    for (l, entry) in LINES.iter().enumerate() {
+        let ofset = channels.ofset(entry.ofset);
+        let mut line = Vec::with_capacity(PACKET_LEN);
+        line.extend_from_slice(&entry.header);
+        for (i, lit) in entry.leds.iter().enumerate() {
+            if !lit {
+                line.push(0);
+            } else {
+                let j = (l % 3) * LEDS_PER_LINE + i;
+                let color = match keys.get(j).and_then(|key| overrides.get(*key)) {
+                    Some(value) => *value,
+                    None => all,
+                };
+                line.push(color_component(color, ofset));
+            }
+        }
+
+        result.push(line);
+    }
+
+    debug_assert!(result.len() <= PACKET_COUNT, "build_table made {} packets", result.len());
+    result
+}
+
+fn has_led(index: usize) -> bool {
+    LINES
+        .get(index / LEDS_PER_LINE)
+        .is_some_and(|line| line.leds[index % LEDS_PER_LINE])
+}
+
+fn self_test(lfos: &LFOS) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if lfos.keys.len() != KEY_COUNT {
+        problems.push(format!("get_keys has {} entries, expected {}", lfos.keys.len(), KEY_COUNT));
+    }
+    for index in lfos.keys.len()..LEDS_PER_LINE * 3 {
+        if has_led(index) {
+            problems.push(format!("index {} has an LED in the BODY masks but is past the end of get_keys", index));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for (index, key) in lfos.keys.iter().enumerate() {
+        if *key != "????" && !seen.insert(key) {
+            problems.push(format!("key '{}' at index {} appears more than once in get_keys", key, index));
+        }
+        if *key == "????" && has_led(index) {
+            problems.push(format!("index {} has an LED in the BODY masks but no key name", index));
+        }
+        if *key != "????" && !has_led(index) {
+            problems.push(format!("key '{}' at index {} has no LED in the BODY masks", key, index));
+        }
+    }
+
+    let mut positions: Vec<_> = lfos.positions.iter().collect();
+    positions.sort();
+    let mut seen = HashMap::new();
+    for (key, position) in positions {
+        if !is_key(lfos, key) || *key == "all" {
+            problems.push(format!("position given for unknown key '{}'", key));
+        }
+        if let Some(other) = seen.insert(position, key) {
+            problems.push(format!("keys '{}' and '{}' share the position {:?}", other, key, position));
+        }
+    }
+    for key in lfos.keys.iter().filter(|key| **key != "????") {
+        if !lfos.positions.contains_key(key) {
+            problems.push(format!("key '{}' has no position in get_key_positions", key));
+        }
+    }
+
+    let mut groups: Vec<_> = lfos.groups.iter().collect();
+    groups.sort();
+    for (group, members) in groups {
+        if is_key(lfos, group) {
+            problems.push(format!("group '{}' has the same name as a key", group));
+        }
+        let mut seen = HashSet::new();
+        for member in members {
+            if member == "all" || !is_key(lfos, member) {
+                problems.push(format!("group '{}' contains unknown key '{}'", group, member));
+            }
+            if !seen.insert(member) {
+                problems.push(format!("group '{}' lists '{}' more than once", group, member));
+            }
+        }
+    }
+    // Typing rows follow the physical rows, numberrow being the second one after esc and the function keys
+    for (row, (name, members)) in get_row_groups().iter().enumerate() {
+        let positions: Vec<_> = members.iter().map(|key| lfos.positions.get(key).copied()).collect();
+        let in_row = positions.iter().all(|position| matches!(position, Some((r, _)) if *r as usize == row + 1));
+        if !in_row || !positions.is_sorted() {
+            problems.push(format!("typing row '{}' isn't the keys of row {} from left to right", name, row + 1));
+        }
+    }
+
+    let mut aliases: Vec<_> = lfos.aliases.iter().collect();
+    aliases.sort();
+    for (alias, key) in aliases {
+        if !is_key(lfos, key) && !lfos.groups.contains_key(*key) {
+            problems.push(format!("alias '{}' points to unknown key '{}'", alias, key));
+        }
+        if is_key(lfos, alias) || lfos.groups.contains_key(*alias) {
+            problems.push(format!("alias '{}' hides a key or group with the same name", alias));
+        }
+    }
+
+    let mut usages: Vec<_> = lfos.usages.iter().collect();
+    usages.sort();
+    let mut seen = HashMap::new();
+    for (usage, key) in usages {
+        if !is_key(lfos, key) || *key == "all" {
+            problems.push(format!("HID usage 0x{:02x} points to unknown key '{}'", usage, key));
+        }
+        if let Some(other) = seen.insert(key, usage) {
+            problems.push(format!("HID usages 0x{:02x} and 0x{:02x} both point to '{}'", other, usage, key));
+        }
+    }
+
+    for theme in theme::THEMES {
+        for (key, color) in theme.colors {
+            if let Err(err) = apply_color(lfos, &mut HashMap::new(), key, color, Blend::Replace, ColorSpace::Rgb) {
+                problems.push(format!("theme '{}': {}", theme.name, err));
+            }
+        }
+    }
+
+    let table = build_table(&lfos.keys, &HashMap::new(), 0, ChannelOrder::Rgb);
+    if table.len() != PACKET_COUNT {
+        problems.push(format!("build_table made {} packets, expected {}", table.len(), PACKET_COUNT));
+    }
+    for (i, packet) in table.iter().enumerate().skip(1) {
+        if LINES.iter().position(|line| packet.starts_with(&line.header)) != Some(i - 1) {
+            problems.push(format!("packet {} can't be told apart from the others by its header", i));
+        }
+    }
+    for (i, packet) in table.iter().enumerate() {
+        if packet.len() != PACKET_LEN {
+            problems.push(format!("packet {} is {} bytes long, expected {}", i, packet.len(), PACKET_LEN));
+        }
+    }
+
+    problems
+}
+
+fn build_lfos_table(
+    lfos: &LFOS,
+    overrides: &HashMap<String, u32>,
+    command: &Command,
+) -> Vec<Vec<u8>> {
+    debug_assert_eq!(lfos.keys.len(), KEY_COUNT, "get_keys no longer lines up with the BODY masks");
+    match &command.calibration {
+        Some(calibration) => build_table(&lfos.keys, &calibrate(lfos, calibration, overrides), 0xffffff, command.channels),
+        None => build_table(&lfos.keys, overrides, 0xffffff, command.channels),
+    }
+}
+
+// Evens out LEDs that are brighter than their neighbours, on the color each key ends up with, one channel at a time
+fn calibrate(lfos: &LFOS, calibration: &Calibration, overrides: &HashMap<String, u32>) -> HashMap<String, u32> {
+    let colors = resolve_colors(lfos, overrides);
+    lfos.keys
+        .iter()
+        .enumerate()
+        .filter_map(|(index, key)| {
+            let color = *colors.get(*key)?;
+            let multipliers = calibration.get(index).copied().unwrap_or([1.0; 3]);
+            let scaled = [16, 8, 0].iter().zip(multipliers).fold(0, |scaled, (ofset, multiplier)| {
+                scaled | ((color_component(color, *ofset) as f64 * multiplier).round() as u32) << ofset
+            });
+            Some((key.to_string(), scaled))
+        })
+        .collect()
+}
+
+fn resolve_colors(lfos: &LFOS, overrides: &HashMap<String, u32>) -> BTreeMap<String, u32> {
+    let all = *overrides.get("all").unwrap_or(&0xffffff);
+    lfos.keys
+        .iter()
+        .filter(|key| **key != "????")
+        .map(|key| (key.to_string(), overrides.get(*key).unwrap_or(&all) & 0xffffff))
+        .collect()
+}
+
+// The colors saved by the last apply, or none with --no-persist
+fn load_state(command: &Command) -> LfosResult<Option<State>> {
+    match command.persist {
+        true => State::load(),
+        false => Ok(None),
+    }
+}
+
+// For commands that change the saved colors instead of replacing them
+fn saved_state(command: &Command) -> LfosResult<State> {
+    if !command.persist {
+        return Err(LfosError::PersistDisabled);
+    }
+    State::load()?.ok_or(LfosError::NoState)
+}
+
+fn save_state(command: &Command, state: &State, keyboard: Option<&Keyboard>) {
+    if !command.persist {
+        return;
+    }
+    if let Err(err) = state.save() {
+        eprintln!("could not save the applied colors: {}", err);
+    }
+    if let Some(keyboard) = keyboard {
+        // The keyboard's own copy also remembers which alternate setting worked, for the next time
+        let state = State {
+            colors: state.colors.clone(),
+            toggled: state.toggled.clone(),
+            alt_setting: Some(keyboard.endpoint.setting),
+        };
+        if let Err(err) = state.save_device(&keyboard.id) {
+            eprintln!("could not save the applied colors for this keyboard: {}", err);
+        }
+    }
+}
+
+fn toggle_keys(state: &mut State, keys: &[String]) {
+    for key in keys {
+        let color = *state.colors.get(key).unwrap_or(&0xffffff);
+        if color != 0 {
+            state.toggled.insert(key.clone(), color);
+            state.colors.insert(key.clone(), 0);
+        } else {
+            let restored = state.toggled.remove(key).unwrap_or(0xffffff);
+            state.colors.insert(key.clone(), restored);
+        }
+    }
+}
+
+// Turns keys off on top of the saved colors, or on top of plain white when nothing was saved
+fn clear_keys(lfos: &LFOS, state: &mut State, keys: &[String]) {
+    if state.colors.is_empty() {
+        state.colors = resolve_colors(lfos, &HashMap::new());
+    }
+    for key in keys {
+        state.colors.insert(key.clone(), 0);
+    }
+}
+
+// Gives keys the saved color of source, or white when it has none, and forgets what they toggled off
+fn copy_color(state: &mut State, source: &str, keys: &[String]) {
+    let color = *state.colors.get(source).unwrap_or(&0xffffff);
+    for key in keys {
+        state.colors.insert(key.clone(), color);
+        state.toggled.remove(key);
+    }
+}
+
+struct Keyboard {
+    handle: DeviceHandle<rusb::Context>,
+    endpoint: Endpoint,
+    split: Vec<Endpoint>,
+    transfer: TransferType,
+    device_desc: DeviceDescriptor,
+    id: String,
+    // The interfaces held for as long as the keyboard is open, and whether a kernel driver was detached from each
+    claimed: Vec<(Endpoint, bool)>,
+    reattach: bool,
+}
+
+impl Keyboard {
+    fn open(command: &Command) -> LfosResult<Keyboard> {
+        let mut backoff = Duration::from_millis(50);
+        for retry in 0.. {
+            match Keyboard::try_open(command) {
+                Ok(keyboard) => {
+                    if retry > 0 {
+                        debug!("opened the keyboard after {} retries", retry);
+                    }
+                    return Ok(keyboard);
+                }
+                Err(LfosError::DeviceBusy { .. }) if retry < command.retry_open => {
+                    debug!("the keyboard is busy, retrying in {:?}", backoff);
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(Duration::from_secs(1));
+                }
+                Err(LfosError::DeviceBusy { .. }) => return Err(LfosError::DeviceBusy { retries: retry }),
+                Err(err) => return Err(err),
+            }
+        }
+        unreachable!()
+    }
+
+    fn try_open(command: &Command) -> LfosResult<Keyboard> {
+        let mut context = rusb::Context::new()?;
+        let (device, device_desc, mut handle) = open_device(&mut context, 0x03f0, 0x1f41)?;
+        let candidates = find_writable_endpoints(&device, command.transfer)?;
+
+        // The bus and address change when the keyboard is plugged in elsewhere, so they're only a fallback
+        let id = match handle.read_serial_number_string_ascii(&device_desc) {
+            Ok(serial) if !serial.trim().is_empty() => serial
+                .trim()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+                .collect(),
+            _ => format!("bus{:03}-address{:03}", device.bus_number(), device.address()),
+        };
+        let split = split_endpoints(&candidates, command.transfer)?;
+        let endpoint = pick_endpoint(&mut handle, candidates, command, &id)?;
+
+        let mut keyboard = Keyboard {
+            handle,
+            endpoint,
+            split,
+            transfer: command.transfer,
+            device_desc,
+            id,
+            claimed: Vec::new(),
+            reattach: command.reattach,
+        };
+        // Claimed once for every packet that follows, and claiming is what fails while another process holds it
+        let mut endpoints: Vec<Endpoint> = Vec::new();
+        for endpoint in std::iter::once(&keyboard.endpoint).chain(&keyboard.split) {
+            if !endpoints.iter().any(|other| other.iface == endpoint.iface) {
+                endpoints.push(endpoint.clone());
+            }
+        }
+        for endpoint in endpoints {
+            let has_kernel_driver = claim_endpoint(&mut keyboard.handle, &endpoint, command.force_detach).map_err(|err| open_error(err.into()))?;
+            keyboard.claimed.push((endpoint, has_kernel_driver));
+        }
+        Ok(keyboard)
+    }
+
+    // Lets go of the interfaces, which Drop does too, for the ways out that skip it like process::exit
+    fn release(&mut self) {
+        for (endpoint, has_kernel_driver) in self.claimed.drain(..) {
+            release_endpoint(&mut self.handle, &endpoint, has_kernel_driver, self.reattach);
+        }
+    }
+
+    fn describe(&self) -> Vec<(&'static str, String)> {
+        let device = self.handle.device();
+        let read = |text: Result<String>| text.unwrap_or_else(|_| "unknown".to_string());
+        vec![
+            (
+                "device",
+                format!(
+                    "{:04x}:{:04x} on bus {}, address {}",
+                    self.device_desc.vendor_id(),
+                    self.device_desc.product_id(),
+                    device.bus_number(),
+                    device.address()
+                ),
+            ),
+            ("manufacturer", read(self.handle.read_manufacturer_string_ascii(&self.device_desc))),
+            ("product", read(self.handle.read_product_string_ascii(&self.device_desc))),
+            ("serial", read(self.handle.read_serial_number_string_ascii(&self.device_desc))),
+            ("id", self.id.clone()),
+            (
+                "endpoint",
+                format!(
+                    "0x{:02x} on interface {}, alternate setting {}",
+                    self.endpoint.address, self.endpoint.iface, self.endpoint.setting
+                ),
+            ),
+        ]
+    }
+
+    // Sends every packet even after one fails, and returns the first error for callers that report a status
+    fn try_write_table(&mut self, command: &Command, table: &[Vec<u8>]) -> LfosResult<()> {
+        let table = &prepare_table(command, table);
+        let mut written = Ok(());
+        for (i, (_, line)) in command.order.packets(table).into_iter().enumerate() {
+            if i > 0 && !command.delay.is_zero() {
+                thread::sleep(command.delay);
+            }
+            let result = self.try_write_packet(command, line);
+            if written.is_ok() {
+                written = result;
+            }
+        }
+        written
+    }
+
+    fn write_table(&mut self, command: &Command, table: &[Vec<u8>]) {
+        if let Err(err) = self.try_write_table(command, table) {
+            report_write_error(err);
+        }
+    }
+
+    // Sends one packet as it is, without anything prepare_table would do to it
+    fn try_write_packet(&mut self, command: &Command, packet: &[u8]) -> LfosResult<()> {
+        // Without any split endpoints every packet goes to the same one, as on the known firmware
+        let endpoint = match self.split.is_empty() {
+            true => &self.endpoint,
+            false => packet_interface(packet)
+                .and_then(|iface| self.split.iter().find(|endpoint| endpoint.iface == iface))
+                .unwrap_or(&self.endpoint),
+        };
+        try_write_endpoint(&self.handle, endpoint, self.transfer, packet).map_err(write_error)?;
+        if let Some(path) = &command.capture {
+            capture_packet(path, packet);
+        }
+        Ok(())
+    }
+
+    fn write_packet(&mut self, command: &Command, packet: &[u8]) {
+        if let Err(err) = self.try_write_packet(command, packet) {
+            report_write_error(err);
+        }
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+fn dim(command: &Command, mut table: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    if command.brightness == 100 {
+        return table;
+    }
+
+    for (packet, line) in table.iter_mut().skip(1).zip(LINES.iter()) {
+        for value in &mut packet[line.header.len()..] {
+            *value = (*value as u32 * command.brightness / 100) as u8;
+        }
+    }
+    table
+}
+
+// Scales every LED by the same factor, so the colors keep their balance while getting dimmer
+fn limit_power(command: &Command, table: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut table = dim(command, table.to_vec());
+    let budget = match command.power_budget {
+        Some(budget) => budget as u64,
+        None => return table,
+    };
+
+    let lit: u64 = LINES.iter().map(|line| line.leds.iter().filter(|lit| **lit).count() as u64).sum();
+    let allowed = lit * 0xff * budget / 100;
+    let total: u64 = table
+        .iter()
+        .skip(1)
+        .zip(LINES.iter())
+        .flat_map(|(packet, line)| &packet[line.header.len()..])
+        .map(|value| *value as u64)
+        .sum();
+    if total <= allowed {
+        return table;
+    }
+
+    let scale = allowed as f64 / total as f64;
+    for (packet, line) in table.iter_mut().skip(1).zip(LINES.iter()) {
+        for value in &mut packet[line.header.len()..] {
+            *value = (*value as f64 * scale) as u8;
+        }
+    }
+    eprintln!("eco: scaled the colors to {:.0}% to stay within the power budget", scale * 100.0);
+
+    table
+}
+
+// Only sends the init packet and the packets carrying one color channel, to check that channel on its own
+fn only_channel(command: &Command, table: Vec<Vec<u8>>, ofset: u8) -> Vec<Vec<u8>> {
+    table
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| *i == 0 || command.channels.ofset(LINES[i - 1].ofset) == ofset)
+        .map(|(_, packet)| packet)
+        .collect()
+}
+
+// Guards the send loop against a table that grew by mistake, nothing past the cap is ever sent
+fn cap_packets(command: &Command, mut table: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    static WARNING: Once = Once::new();
+    if table.len() > command.max_packets {
+        WARNING.call_once(|| {
+            eprintln!(
+                "warning: a table of {} packets is over the limit of {}, the rest won't be sent",
+                table.len(),
+                command.max_packets
+            )
+        });
+        table.truncate(command.max_packets);
+    }
+    table
+}
+
+fn prepare_table(command: &Command, table: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let table = cap_packets(command, limit_power(command, table));
+    let mut table = match command.only_channel {
+        Some(ofset) => only_channel(command, table, ofset),
+        None => table,
+    };
+    // --init-hex goes in last, the steps above count on the init packet being the first one
+    if let Some(first) = table.first_mut() {
+        first.clone_from(&command.init);
+    }
+    table
+}
+
+fn print_table(command: &Command, table: &[Vec<u8>]) {
+    let table = &prepare_table(command, table);
+    let packets = command.order.packets(table);
+    let lines: Vec<String> = if command.table_hex {
+        vec![packets.iter().map(|(_, packet)| to_hex(packet)).collect()]
+    } else {
+        packets
+            .iter()
+            .map(|(index, packet)| format!("packet {}: {}", index, to_hex(packet)))
+            .collect()
+    };
+
+    // Stands in for the time the keyboard would take to accept each packet
+    if !command.latency.is_zero() {
+        thread::sleep(command.latency * packets.len() as u32);
+    }
+
+    match &command.output_file {
+        Some(path) => {
+            if let Err(err) = append_line(path, &lines.join("\n")) {
+                eprintln!("could not write the packets to '{}': {}", path.display(), err);
+            }
+        }
+        None => println!("{}", lines.join("\n")),
+    }
+}
+
+fn apply_table(command: &Command, table: &[Vec<u8>]) -> LfosResult<()> {
+    if command.dry_run {
+        print_table(command, table);
+        return Ok(());
+    }
+    Keyboard::open(command)?.write_table(command, table);
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(lfos: &LFOS, command: &Command) -> LfosResult<()> {
+    tui::run(lfos, command)
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui(_lfos: &LFOS, _command: &Command) -> LfosResult<()> {
+    Err(LfosError::FeatureDisabled {
+        command: "tui".to_string(),
+        feature: "tui".to_string(),
+    })
+}
+
+#[cfg(feature = "mqtt")]
+fn run_mqtt(lfos: &LFOS, command: &Command, broker: &str, topic: &str) -> LfosResult<()> {
+    mqtt::run(lfos, command, broker, topic)
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn run_mqtt(_lfos: &LFOS, _command: &Command, _broker: &str, _topic: &str) -> LfosResult<()> {
+    Err(LfosError::FeatureDisabled {
+        command: "mqtt".to_string(),
+        feature: "mqtt".to_string(),
+    })
+}
+
+#[cfg(feature = "audio")]
+fn run_audio(lfos: &LFOS, command: &Command, keys: &[String], low: u32, high: u32) -> LfosResult<()> {
+    audio::run(lfos, command, keys, low, high)
+}
+
+#[cfg(not(feature = "audio"))]
+fn run_audio(_lfos: &LFOS, _command: &Command, _keys: &[String], _low: u32, _high: u32) -> LfosResult<()> {
+    Err(LfosError::FeatureDisabled {
+        command: "effect audio".to_string(),
+        feature: "audio".to_string(),
+    })
+}
+
+#[cfg(feature = "screen")]
+fn run_accent(lfos: &LFOS, command: &Command, keys: &[String], interval: Option<Duration>) -> LfosResult<()> {
+    screen::run(lfos, command, keys, interval)
+}
+
+#[cfg(not(feature = "screen"))]
+fn run_accent(_lfos: &LFOS, _command: &Command, _keys: &[String], _interval: Option<Duration>) -> LfosResult<()> {
+    Err(LfosError::FeatureDisabled {
+        command: "accent --from-screen".to_string(),
+        feature: "screen".to_string(),
+    })
+}
+
+fn reset_table(lfos: &LFOS, command: &Command) -> Option<Vec<Vec<u8>>> {
+    let color = command.reset?;
+    let overrides = HashMap::from([("all".to_string(), color)]);
+    Some(build_lfos_table(lfos, &overrides, command))
+}
+
+fn reset_keyboard(lfos: &LFOS, command: &Command) {
+    if let Some(table) = reset_table(lfos, command) {
+        if let Err(err) = apply_table(command, &table) {
+            eprintln!("could not reset the keyboard: {}", err);
+        }
+    }
+}
+
+// Applies --reset-on-exit however run returns, including on errors
+struct ResetGuard<'a> {
+    lfos: &'a LFOS,
+    command: &'a Command,
+}
+
+impl Drop for ResetGuard<'_> {
+    fn drop(&mut self) {
+        if self.command.action.uses_keyboard() {
+            reset_keyboard(self.lfos, self.command);
+        }
+    }
+}
+
+fn reset_on_interrupt(command: &Command) -> LfosResult<()> {
+    let command = command.clone();
+    ctrlc::set_handler(move || {
+        reset_keyboard(&get_lfos(), &command);
+        std::process::exit(130);
+    })
+    .map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+fn default_pipe_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(concat!(env!("CARGO_PKG_NAME"), ".pipe"))
+}
+
+#[cfg(unix)]
+fn hand_off(command: &Command, state: &State) -> LfosResult<bool> {
+    serve::hand_off(command, state)
+}
+
+#[cfg(not(unix))]
+fn hand_off(_command: &Command, _state: &State) -> LfosResult<bool> {
+    Ok(false)
+}
+
+// Keys whose color isn't the saved one, counting keys that weren't saved at all
+fn changed_keys(saved: &BTreeMap<String, u32>, colors: &BTreeMap<String, u32>) -> usize {
+    colors.iter().filter(|(key, color)| saved.get(*key) != Some(color)).count()
+}
+
+// Asks before sending, with a line on what's about to change. Without a terminal to ask on, like in scripts, it goes
+// ahead as if the answer was yes
+fn confirm(saved: Option<&State>, state: &State) -> LfosResult<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    let was_off = |key: &String| saved.is_some_and(|saved| saved.colors.get(key) == Some(&0));
+    let off = state.colors.iter().filter(|(key, color)| **color == 0 && !was_off(key)).count();
+    let summary = match saved {
+        Some(saved) => format!("{} keys will change", changed_keys(&saved.colors, &state.colors)),
+        None => format!("all {} keys will be set", state.colors.len()),
+    };
+    eprint!("{}, {} of them turned off. Apply? [y/N] ", summary, off);
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn apply_state(lfos: &LFOS, command: &Command, state: &State) -> LfosResult<()> {
+    if command.show_overrides {
+        // Printed as a profile, so it can be fed back with --profile -
+        for (key, color) in &state.colors {
+            println!("{}", profile_entry(key, &format!("{:06x}", color)));
+        }
+    }
+    let asking = command.confirm && !command.dry_run;
+    let saved = match command.since_state || asking {
+        true => load_state(command)?,
+        false => None,
+    };
+    if command.since_state {
+        match &saved {
+            Some(saved) => eprintln!("since-state: {} keys changed", changed_keys(&saved.colors, &state.colors)),
+            None => eprintln!("since-state: no saved colors, full apply"),
+        }
+    }
+    if asking && !confirm(saved.as_ref(), state)? {
+        eprintln!("nothing was applied");
+        return Ok(());
+    }
+    if !command.dry_run && !command.hold && hand_off(command, state)? {
+        return Ok(());
+    }
+
+    let overrides = state.colors.clone().into_iter().collect();
+    let table = build_lfos_table(lfos, &overrides, command);
+    // Going through black first clears keys that keep a stale color from an earlier scene
+    let black = command
+        .start_black
+        .then(|| build_lfos_table(lfos, &HashMap::from([("all".to_string(), 0)]), command));
+    if command.dry_run {
+        if let Some(black) = &black {
+            print_table(command, black);
+        }
+        return apply_table(command, &table);
+    }
+
+    let mut keyboard = Keyboard::open(command)?;
+    if let Some(black) = &black {
+        keyboard.write_table(command, black);
+    }
+    keyboard.write_table(command, &table);
+    save_state(command, state, Some(&keyboard));
+    Ok(())
+}
+
+fn run_info(command: &Command) -> LfosResult<()> {
+    let keyboard = Keyboard::open(command)?;
+    for (name, value) in keyboard.describe() {
+        println!("{}: {}", name, value);
+    }
+
+    println!("Saved keyboards:");
+    for id in state::device_ids() {
+        let current = if id == keyboard.id { " (this one)" } else { "" };
+        println!("\t{}{}", id, current);
+    }
+    Ok(())
+}
+
+// Goes through the usual reasons colors don't show up, one line per check, and stops at the first one that
+// leaves nothing to check further
+fn run_doctor(command: &Command, config_error: Option<&LfosError>) -> LfosResult<()> {
+    let mut failed = 0;
+    let mut check = |name: &str, result: std::result::Result<String, String>| match result {
+        Ok(detail) => println!("[ok]   {}: {}", name, detail),
+        Err(detail) => {
+            println!("[FAIL] {}: {}", name, detail);
+            failed += 1;
+        }
+    };
+
+    // Everything below runs with the defaults when the config can't be read
+    let config = match (config_error, config::config_path()) {
+        (Some(err), _) => Err(err.to_string()),
+        (None, Some(path)) if path.exists() => Ok(format!("read {}", path.display())),
+        (None, Some(path)) => Ok(format!("none at {}, the defaults are used", path.display())),
+        (None, None) => Ok("no config directory on this system, the defaults are used".to_string()),
+    };
+    check("config", config);
+
+    let mut context = match rusb::Context::new() {
+        Ok(context) => context,
+        Err(err) => {
+            check("usb", Err(format!("could not start USB: {}", err)));
+            return Err(LfosError::Doctor { failed });
+        }
+    };
+    let (device, _, mut handle) = match open_device(&mut context, 0x03f0, 0x1f41) {
+        Ok(opened) => opened,
+        Err(LfosError::DeviceNotFound) => {
+            check("device", Err("no Omen Sequencer (03f0:1f41) is connected, check the cable and try another port".to_string()));
+            return Err(LfosError::Doctor { failed });
+        }
+        Err(err) => {
+            check("device", Ok("found 03f0:1f41".to_string()));
+            let hint = match err {
+                LfosError::AccessDenied => PERMISSION_HINT,
+                LfosError::DeviceBusy { .. } => "another program is using it, close it or use --retry-open",
+                _ => "try unplugging it and plugging it back in",
+            };
+            check("permission", Err(format!("could not open it: {}", hint)));
+            return Err(LfosError::Doctor { failed });
+        }
+    };
+    check(
+        "device",
+        Ok(format!("found 03f0:1f41 on bus {}, address {}", device.bus_number(), device.address())),
+    );
+    check("permission", Ok("the keyboard can be opened".to_string()));
+
+    // No endpoint is reported as a failed check further down
+    let endpoints = find_writable_endpoints(&device, command.transfer).unwrap_or_default();
+    let mut interfaces: Vec<u8> = endpoints.iter().map(|endpoint| endpoint.iface).collect();
+    interfaces.dedup();
+    for iface in interfaces {
+        let driver = match handle.kernel_driver_active(iface) {
+            Ok(true) => Ok(format!("attached to interface {}, it is detached while the keyboard is open and attached again after", iface)),
+            Ok(false) => Ok(format!("none on interface {}", iface)),
+            Err(rusb::Error::NotSupported) => Ok("not used on this platform".to_string()),
+            Err(err) => Err(format!("could not check interface {}: {}", iface, err)),
+        };
+        check("kernel driver", driver);
+    }
+
+    let transfer = transfer_name(command.transfer);
+    let endpoint = match endpoints.first() {
+        Some(endpoint) => Ok(format!(
+            "{} writable {} endpoint(s), the first is 0x{:02x} on interface {}",
+            endpoints.len(),
+            transfer,
+            endpoint.address,
+            endpoint.iface
+        )),
+        None => {
+            let other = if transfer == "bulk" { "interrupt" } else { "bulk" };
+            Err(format!("no writable {} endpoint, try --transfer {}", transfer, other))
+        }
+    };
+    check("endpoint", endpoint);
+
+    // Opening isn't enough, claiming the interface to write to it can still be refused. The init packet on its own
+    // doesn't change any colors
+    if let Some(endpoint) = endpoints.first() {
+        let written = write_endpoint_once(&mut handle, endpoint, command, &command.init);
+        let write = match written.map_err(|err| open_error(err.into())) {
+            Ok(()) => Ok("the keyboard took the init packet".to_string()),
+            Err(LfosError::AccessDenied) => Err(format!("could not claim the interface: {}", PERMISSION_HINT)),
+            Err(err @ LfosError::DeviceBusy { .. }) => Err(err.to_string()),
+            Err(err) => Err(format!("could not write the init packet ({}), try unplugging it and plugging it back in", err)),
+        };
+        check("write", write);
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(LfosError::Doctor { failed }),
+    }
+}
+
+// Times whole applies on the real keyboard, opened once, the same way every other command writes to it
+fn run_bench_apply(lfos: &LFOS, command: &Command, runs: u32) -> LfosResult<()> {
+    let mut keyboard = Keyboard::open(command)?;
+    let table = build_lfos_table(lfos, &command.overrides, command);
+
+    let mut times: Vec<Duration> = (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            keyboard.try_write_table(command, &table)?;
+            Ok(start.elapsed())
+        })
+        .collect::<LfosResult<_>>()?;
+    times.sort();
+
+    let ms = |time: &Duration| format!("{:.2} ms", time.as_secs_f64() * 1000.0);
+    println!("bench-apply: {} applies of {} packets", runs, prepare_table(command, &table).len());
+    println!("min: {}", ms(&times[0]));
+    println!("median: {}", ms(&times[times.len() / 2]));
+    println!("max: {}", ms(&times[times.len() - 1]));
+
+    let state = State {
+        colors: resolve_colors(lfos, &command.overrides),
+        ..State::default()
+    };
+    save_state(command, &state, Some(&keyboard));
+    Ok(())
+}
+
+fn run_restore(lfos: &LFOS, command: &Command) -> LfosResult<()> {
+    if !command.persist {
+        return Err(LfosError::PersistDisabled);
+    }
+    let mut keyboard = Keyboard::open(command)?;
+    let state = match State::load_device(&keyboard.id)? {
+        Some(state) => state,
+        None => saved_state(command)?,
+    };
+
+    let overrides = state.colors.clone().into_iter().collect();
+    let table = build_lfos_table(lfos, &overrides, command);
+    if command.dry_run {
+        print_table(command, &table);
+        return Ok(());
+    }
+    keyboard.write_table(command, &table);
+    save_state(command, &state, Some(&keyboard));
+    Ok(())
+}
+
+fn run_pick(lfos: &LFOS, command: &Command, keys: &[String]) -> LfosResult<()> {
+    // The keyboard stays open between colors, so each one shows up right away
+    let mut keyboard = if command.dry_run { None } else { Some(Keyboard::open(command)?) };
+    let mut state = load_state(command)?.unwrap_or_default();
+    if state.colors.is_empty() {
+        state.colors = resolve_colors(lfos, &HashMap::new());
+    }
+
+    let mut picked = false;
+    loop {
+        eprint!("color for {} (or done): ", keys.join(", "));
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            // Ctrl-D finishes like done does
+            eprintln!();
+            break;
+        }
+        let line = line.trim();
+        if line == "done" {
+            break;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let color = match parse_color(line) {
+            Ok(color) => color,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+        for key in keys {
+            state.colors.insert(key.clone(), color);
+            state.toggled.remove(key);
+        }
+        let overrides = state.colors.clone().into_iter().collect();
+        let table = build_lfos_table(lfos, &overrides, command);
+        match &mut keyboard {
+            Some(keyboard) => keyboard.write_table(command, &table),
+            None => print_table(command, &table),
+        }
+        picked = true;
+    }
+
+    if let (true, Some(keyboard)) = (picked, &keyboard) {
+        save_state(command, &state, Some(keyboard));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn run_serve(lfos: &LFOS, command: &Command, path: &Path) -> LfosResult<()> {
+    serve::serve(lfos, command, path)
+}
+
+#[cfg(not(unix))]
+fn run_serve(_lfos: &LFOS, _command: &Command, _path: &Path) -> LfosResult<()> {
+    Err(LfosError::Unsupported("serve --pipe".to_string()))
+}
+
+pub fn json_errors(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--json-errors")
+        || args.windows(2).any(|pair| pair[0] == "--format" && pair[1] == "json")
+}
+
+pub fn run(args: &[String]) -> LfosResult<()> {
+    let lfos = &get_lfos();
+    let default_profile = std::env::var_os(LFOS_DEFAULT_PROFILE).is_some_and(|path| !path.is_empty());
+    // Asking for help shouldn't depend on a config file that may be what needs the help
+    show_help_or_version(lfos, args);
+    // selftest doesn't use the config, and doctor is what someone runs to find out what's wrong with it
+    let diagnosing = args.iter().skip(1).any(|arg| arg == "selftest" || arg == "doctor");
+    let (config, config_error) = match Config::load() {
+        Ok(config) => (config, None),
+        Err(err) if diagnosing => (Config::default(), Some(err)),
+        Err(err) => return Err(err),
+    };
+    // Only a call without any arguments runs the default command, so it can't end up running itself
+    let args = match &config.default_command {
+        Some(default_command) if args.len() <= 1 && !default_profile => {
+            args.iter().take(1).chain(default_command).cloned().collect()
+        }
+        _ => args.to_vec(),
+    };
+    let command = try_parse_cmd(lfos, &args, &config)?;
+    if let Some(path) = &command.output_file {
+        // Every run starts the file over, so it can be compared against an earlier run
+        std::fs::write(path, "")?;
+    }
+    let _reset = ResetGuard {
+        lfos,
+        command: &command,
+    };
+    // serve and effects have their own Ctrl-C handlers, and reset once those are done
+    let loops = command.hold
+        || matches!(
+            command.action,
+            Action::Serve(_) | Action::Mqtt { .. } | Action::Effect(_) | Action::Audio { .. } | Action::Replay { .. } | Action::Accent { interval: Some(_), .. }
+        );
+    if command.reset.is_some() && command.action.uses_keyboard() && !loops {
+        reset_on_interrupt(&command)?;
+    }
+
+    match command.action {
+        Action::Apply => {
+            let state = State {
+                colors: resolve_colors(lfos, &command.overrides),
+                ..State::default()
+            };
+            apply_state(lfos, &command, &state)?;
+            if command.hold && !command.dry_run {
+                return run_serve(lfos, &command, &default_pipe_path());
+            }
+            Ok(())
+        }
+        Action::Toggle(ref keys) => {
+            let mut state = saved_state(&command)?;
+            toggle_keys(&mut state, keys);
+            apply_state(lfos, &command, &state)
+        }
+        Action::Clear(ref keys) => {
+            // Only --merge gets here, and it's all about the saved colors
+            if !command.persist {
+                return Err(LfosError::PersistDisabled);
+            }
+            let mut state = load_state(&command)?.unwrap_or_default();
+            clear_keys(lfos, &mut state, keys);
+            apply_state(lfos, &command, &state)
+        }
+        Action::HueShift(degrees) => {
+            let colors = given_or_saved_colors(lfos, &command)?
+                .into_iter()
+                .map(|(key, color)| (key, hue_shift(color, degrees)))
+                .collect();
+            apply_state(lfos, &command, &State {
+                colors,
+                ..State::default()
+            })
+        }
+        Action::Serve(ref path) => run_serve(lfos, &command, path),
+        Action::Mqtt { ref broker, ref topic } => run_mqtt(lfos, &command, broker, topic),
+        Action::Tui => run_tui(lfos, &command),
+        Action::Validate(ref path) => run_validate(lfos, &command, path),
+        Action::Export => run_export(lfos, &command),
+        Action::Share => {
+            let all = command.overrides.get("all").copied().unwrap_or(0xffffff);
+            println!("{}", share::encode(lfos, all, &given_or_saved_colors(lfos, &command)?));
+            Ok(())
+        }
+        Action::Info => run_info(&command),
+        Action::Doctor => run_doctor(&command, config_error.as_ref()),
+        Action::NewProfile { ref path, force } => run_new_profile(lfos, path, force),
+        Action::Pulse { ref keys, color, duration } => effect::pulse(lfos, &command, keys, color, duration),
+        Action::Index(ref keys) => {
+            run_index(lfos, keys);
+            Ok(())
+        }
+        Action::Copy { ref source, ref keys } => {
+            let mut state = saved_state(&command)?;
+            copy_color(&mut state, source, keys);
+            apply_state(lfos, &command, &state)
+        }
+        Action::Pick(ref keys) => run_pick(lfos, &command, keys),
+        Action::Layout => {
+            run_layout(lfos, &command);
+            Ok(())
+        }
+        Action::Colors => {
+            run_colors();
+            Ok(())
+        }
+        Action::Restore => run_restore(lfos, &command),
+        Action::BenchApply(runs) => run_bench_apply(lfos, &command, runs),
+        Action::Replay { ref path, looped } => replay::run(lfos, &command, path, looped),
+        Action::VerifyGolden { ref path, update } => {
+            golden::verify(path, &build_lfos_table(lfos, &command.overrides, &command), update)
+        }
+        Action::Effect(ref effect) => effect::run(lfos, &command, effect),
+        Action::Accent { ref keys, interval } => run_accent(lfos, &command, keys, interval),
+        Action::Audio { ref keys, low, high } => run_audio(lfos, &command, keys, low, high),
+        Action::Svg { ref path, key_size, gap } => run_svg(lfos, &command, path, key_size, gap),
+        Action::SelfTest => {
+            let problems = self_test(lfos);
+            if !problems.is_empty() {
+                return Err(LfosError::SelfTest(problems));
+            }
+            println!("selftest: all checks passed");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(lfos: &LFOS, name: &str) -> Option<usize> {
+        let key = resolve_key(lfos, name);
+        lfos.keys.iter().position(|other| *other == key)
+    }
+
+    #[test]
+    fn aliases_resolve_to_the_index_of_their_key() {
+        let lfos = get_lfos();
+        for (alias, key) in &lfos.aliases {
+            assert!(index(&lfos, key).is_some(), "alias '{}' points to '{}', which isn't in get_keys", alias, key);
+            assert_eq!(index(&lfos, alias), index(&lfos, key), "alias '{}'", alias);
+        }
+    }
+
+    #[test]
+    fn get_keys_has_key_count_entries() {
+        assert_eq!(get_keys().len(), KEY_COUNT);
+    }
+
+    #[test]
+    fn get_keys_names_each_key_once() {
+        let mut seen = HashSet::new();
+        for key in get_keys().into_iter().filter(|key| *key != "????") {
+            assert!(seen.insert(key), "{} is listed twice", key);
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        std::iter::once(LFOS_NAME).chain(args.iter().copied()).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn numeric_options_name_the_option_and_value() {
+        let lfos = get_lfos();
+        let options = ["--delay-ms", "--simulate-latency", "--interval-ms", "--duration-ms", "--hold-ms", "--fade-ms", "--alt-setting"];
+        for option in options {
+            match try_parse_cmd(&lfos, &args(&[option, "-5", "--dry-run"]), &Config::default()) {
+                Err(LfosError::InvalidValue { option: named, value, .. }) => {
+                    assert_eq!((named.as_str(), value.as_str()), (option, "-5"));
+                }
+                other => panic!("{} -5 should be an invalid value, got: {:?}", option, other.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn selftest_passes() {
+        let problems = self_test(&get_lfos());
+        assert!(problems.is_empty(), "{:#?}", problems);
+    }
+
+    #[test]
+    fn red_goes_to_the_packets_of_the_red_channel() {
+        let lfos = get_lfos();
+        let red = HashMap::from([("all".to_string(), 0xff0000)]);
+        for (channels, packets) in [(ChannelOrder::Rgb, 1..=3), (ChannelOrder::Bgr, 7..=9), (ChannelOrder::Grb, 4..=6)] {
+            let table = build_table(&lfos.keys, &red, 0, channels);
+            for (i, packet) in table.iter().enumerate().skip(1) {
+                let lit = packet[LINES[i - 1].header.len()..].iter().any(|value| *value != 0);
+                assert_eq!(lit, packets.contains(&i), "{:?}, packet {}", channels, i);
+            }
+        }
+    }
+
+    #[test]
+    fn build_table_makes_packet_count_packets_of_packet_len_bytes() {
+        let lfos = get_lfos();
+        for channels in [ChannelOrder::Rgb, ChannelOrder::Bgr, ChannelOrder::Grb] {
+            let table = build_table(&lfos.keys, &HashMap::new(), 0xffffff, channels);
+            assert_eq!(table.len(), PACKET_COUNT, "{:?}", channels);
+            for (i, packet) in table.iter().enumerate() {
+                assert_eq!(packet.len(), PACKET_LEN, "{:?}, packet {}", channels, i);
+            }
+        }
+    }
+
+    #[test]
+    fn build_table_gives_leds_past_the_end_of_the_keys_the_all_color() {
+        let lfos = get_lfos();
+        let mut overrides: HashMap<String, u32> = lfos.keys.iter().map(|key| (key.to_string(), 0x0000ff)).collect();
+        overrides.insert("all".to_string(), 0xff0000);
+        overrides.insert("nokey".to_string(), 0x00ff00);
+        let keys = &lfos.keys[..10];
+
+        let table = build_table(keys, &overrides, 0, ChannelOrder::Rgb);
+        assert_eq!(table.len(), PACKET_COUNT);
+        for (l, (packet, line)) in table.iter().skip(1).zip(LINES.iter()).enumerate() {
+            for (i, value) in packet[line.header.len()..].iter().enumerate() {
+                let j = (l % 3) * LEDS_PER_LINE + i;
+                let expected = match (has_led(j), j < keys.len()) {
+                    (false, _) => 0,
+                    (true, true) => color_component(0x0000ff, line.ofset),
+                    (true, false) => color_component(0xff0000, line.ofset),
+                };
+                assert_eq!(*value, expected, "packet {}, index {}", l + 1, j);
+            }
+        }
+    }
+
+    #[test]
+    fn options_a_helper_would_drop_are_kept_from_it() {
+        let lfos = get_lfos();
+        let unshared = |given: &[&str], config: &Config| try_parse_cmd(&lfos, &args(given), config).unwrap().unshared;
+        assert!(unshared(&["home", "red"], &Config::default()).is_empty());
+        assert_eq!(unshared(&["--brightness", "50", "--start-black", "home", "red"], &Config::default()), ["--brightness", "--start-black"]);
+        assert_eq!(unshared(&["--channel-order", "bgr", "home", "red"], &Config::default()), ["--channel-order"]);
+
+        // The helper reads the same config file, so its brightness is already the one the helper uses
+        let config = Config {
+            brightness: Some(50),
+            ..Config::default()
+        };
+        assert!(unshared(&["home", "red"], &config).is_empty());
+    }
+
+    #[test]
+    fn extras_expands_to_the_media_and_p_keys() {
+        let lfos = get_lfos();
+        let extras = expand_keys(&lfos, "extras").unwrap();
+        assert_eq!(extras, ["play", "stop", "playlast", "playnext", "p1", "p2", "p3", "p4", "p5"]);
+    }
+
+    #[test]
+    fn clear_with_merge_keeps_the_saved_colors_of_other_keys() {
+        let lfos = get_lfos();
+        let mut state = State {
+            colors: resolve_colors(&lfos, &HashMap::from([("all".to_string(), 0x0000ff), ("home".to_string(), 0xff0000)])),
+            ..State::default()
+        };
+        let fkeys = expand_keys(&lfos, "fkeys").unwrap();
+        clear_keys(&lfos, &mut state, &fkeys);
+        for (key, color) in &state.colors {
+            let expected = match key.as_str() {
+                "home" => 0xff0000,
+                key if fkeys.iter().any(|fkey| fkey == key) => 0,
+                _ => 0x0000ff,
+            };
+            assert_eq!(*color, expected, "{}", key);
+        }
+    }
+
+    #[test]
+    fn clear_without_merge_turns_every_other_key_white() {
+        let lfos = get_lfos();
+        let command = try_parse_cmd(&lfos, &args(&["clear", "fkeys"]), &Config::default()).unwrap();
+        assert!(matches!(command.action, Action::Apply));
+        let fkeys = expand_keys(&lfos, "fkeys").unwrap();
+        for (key, color) in resolve_colors(&lfos, &command.overrides) {
+            assert_eq!(color, if fkeys.contains(&key) { 0 } else { 0xffffff }, "{}", key);
+        }
+    }
+
+    struct MockDevice(Vec<EndpointInfo>);
+
+    impl UsbDevice for MockDevice {
+        fn endpoints(&self) -> Vec<EndpointInfo> {
+            self.0.clone()
+        }
+    }
+
+    fn endpoint_info(address: u8, direction: Direction, transfer: TransferType) -> EndpointInfo {
+        EndpointInfo {
+            endpoint: Endpoint {
+                config: 1,
+                iface: 0,
+                setting: 0,
+                address,
+            },
+            direction,
+            transfer,
+        }
+    }
+
+    #[test]
+    fn no_writable_endpoint_says_what_to_try() {
+        let device = MockDevice(vec![
+            endpoint_info(0x81, Direction::In, TransferType::Interrupt),
+            endpoint_info(0x02, Direction::Out, TransferType::Bulk),
+        ]);
+        match find_writable_endpoints(&device, TransferType::Interrupt) {
+            Err(err @ LfosError::NoEndpoint { .. }) => {
+                assert!(err.to_string().ends_with("try --transfer bulk or --force-detach"), "{}", err);
+            }
+            other => panic!("expected no_endpoint, got: {:?}", other),
+        }
+
+        let bulk = find_writable_endpoints(&device, TransferType::Bulk).unwrap();
+        assert_eq!(bulk.iter().map(|endpoint| endpoint.address).collect::<Vec<_>>(), [0x02]);
+    }
+
+    #[test]
+    fn hex_colors_take_css_and_c_prefixes() {
+        for text in ["ff8000", "#ff8000", "0xff8000", "0XFF8000"] {
+            assert_eq!(parse_color(text).unwrap(), 0xff8000, "{}", text);
+        }
+        assert_eq!(parse_color("#f80").unwrap(), 0xff8800);
+    }
+
+    #[test]
+    fn malformed_hex_colors_are_turned_down() {
+        for text in ["", "#", "0x", "#gg8000", "ff80zz", "ff 8000", "##ff8000", "1ffffffff", "16711680", "ff0000ff", "0x1ffffff", "+ff0000", "fff", "0xfff", "ff800"] {
+            assert!(matches!(parse_color(text), Err(LfosError::InvalidColor { .. })), "{}", text);
+        }
+    }
+
+    // A file in the temp directory that is removed again when the test is done with it
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, text: &str) -> TempFile {
+            let path = std::env::temp_dir().join(format!("{}-{}-{}", LFOS_NAME, std::process::id(), name));
+            std::fs::write(&path, text).unwrap();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn later_profiles_win_and_the_command_line_wins_over_them() {
+        let lfos = get_lfos();
+        let base = TempFile::new("base.toml", "all = \"0000ff\"\nhome = \"00ff00\"\npgup = \"00ff00\"\n");
+        let accent = TempFile::new("accent.toml", "home = \"ff0000\"\nesc = \"ffff00\"\n");
+        let given = args(&["--profile", base.path(), "--profile", accent.path(), "end", "ffffff"]);
+
+        let colors = resolve_colors(&lfos, &try_parse_cmd(&lfos, &given, &Config::default()).unwrap().overrides);
+        assert_eq!(colors["home"], 0xff0000, "the overlapping key comes from the later profile");
+        assert_eq!(colors["esc"], 0xffff00, "keys only in the later profile are added");
+        assert_eq!(colors["end"], 0xffffff, "keys on the command line win over every profile");
+        assert_eq!(colors["pgup"], 0x00ff00, "keys only in the earlier profile are kept");
+        assert_eq!(colors["a"], 0x0000ff);
+    }
+
+    #[test]
+    fn brightness_comes_from_the_command_line_then_the_config_file() {
+        let lfos = get_lfos();
+        let brightness = |given: &[&str], config: &Config| try_parse_cmd(&lfos, &args(given), config).unwrap().brightness;
+        let config = Config {
+            brightness: Some(60),
+            ..Config::default()
+        };
+        assert_eq!(brightness(&["home", "red"], &Config::default()), 100);
+        assert_eq!(brightness(&["home", "red"], &config), 60);
+        assert_eq!(brightness(&["--brightness", "80", "home", "red"], &config), 80);
+    }
+
+    #[test]
+    fn calibration_scales_the_color_each_key_ends_up_with() {
+        let lfos = get_lfos();
+        let mut command = try_parse_cmd(&lfos, &args(&["solid", "ffffff", "home", "808080"]), &Config::default()).unwrap();
+        let plain = build_lfos_table(&lfos, &command.overrides, &command);
+
+        command.calibration = Some(vec![[1.0; 3]; lfos.keys.len()]);
+        assert_eq!(build_lfos_table(&lfos, &command.overrides, &command), plain);
+
+        let mut calibration = vec![[1.0; 3]; lfos.keys.len()];
+        calibration[index(&lfos, "home").unwrap()] = [0.5, 1.0, 0.0];
+        command.calibration = Some(calibration);
+        let expected = HashMap::from([("all".to_string(), 0xffffff), ("home".to_string(), 0x408000)]);
+        assert_eq!(build_lfos_table(&lfos, &command.overrides, &command), build_table(&lfos.keys, &expected, 0xffffff, ChannelOrder::Rgb));
+    }
+
+    #[test]
+    fn copy_gives_keys_the_color_of_the_source() {
+        let mut state = State {
+            colors: BTreeMap::from([("home".to_string(), 0xff0000), ("end".to_string(), 0x00ff00), ("f1".to_string(), 0)]),
+            toggled: BTreeMap::from([("f1".to_string(), 0x0000ff)]),
+            ..State::default()
+        };
+        copy_color(&mut state, "home", &["end".to_string(), "f1".to_string()]);
+        assert_eq!(state.colors["home"], 0xff0000);
+        assert_eq!(state.colors["end"], 0xff0000);
+        assert_eq!(state.colors["f1"], 0xff0000);
+        assert!(state.toggled.is_empty());
+
+        copy_color(&mut state, "pgup", &["end".to_string()]);
+        assert_eq!(state.colors["end"], 0xffffff);
+    }
+
+    // Succeeds at everything but the alternate setting, which fails with the given error
+    struct MockHandle(rusb::Error);
+
+    impl UsbHandle for MockHandle {
+        fn set_active_configuration(&mut self, _config: u8) -> Result<()> {
+            Ok(())
+        }
+
+        fn claim_interface(&mut self, _iface: u8) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_alternate_setting(&mut self, _iface: u8, _setting: u8) -> Result<()> {
+            Err(self.0)
+        }
+    }
+
+    #[test]
+    fn refusing_the_default_alternate_setting_is_not_an_error() {
+        let mut endpoint = endpoint_info(0x02, Direction::Out, TransferType::Interrupt).endpoint;
+        for err in [rusb::Error::NotSupported, rusb::Error::InvalidParam] {
+            assert_eq!(configure_endpoint(&mut MockHandle(err), &endpoint), Ok(()), "{:?}", err);
+        }
+        assert_eq!(configure_endpoint(&mut MockHandle(rusb::Error::Io), &endpoint), Err(rusb::Error::Io));
+
+        endpoint.setting = 1;
+        for err in [rusb::Error::NotSupported, rusb::Error::InvalidParam] {
+            assert_eq!(configure_endpoint(&mut MockHandle(err), &endpoint), Err(err));
+        }
+    }
+
+    #[test]
+    fn config_colors_sit_below_all_and_the_keys_given() {
+        let lfos = get_lfos();
+        let config = Config {
+            colors: vec![("numpad".to_string(), "00ff00".to_string()), ("media".to_string(), "0000ff".to_string())],
+            ..Config::default()
+        };
+        let colors = |given: &[&str]| resolve_colors(&lfos, &try_parse_cmd(&lfos, &args(given), &config).unwrap().overrides);
+
+        let defaults = colors(&["numpad5", "ff0000"]);
+        assert_eq!(defaults["numpad5"], 0xff0000, "a key given inside a group wins over the group default");
+        assert_eq!(defaults["numpad1"], 0x00ff00);
+        assert_eq!(defaults["play"], 0x0000ff);
+        assert_eq!(defaults["a"], 0xffffff);
+
+        let all = colors(&["all", "808080", "numpad5", "ff0000"]);
+        assert_eq!(all["numpad5"], 0xff0000);
+        assert_eq!(all["numpad1"], 0x808080, "all wins over the group default");
+        assert_eq!(all["play"], 0x808080);
+
+        let group = colors(&["numpad", "ff0000"]);
+        assert_eq!(group["numpad1"], 0xff0000, "a group given wins over its default");
+        assert_eq!(group["play"], 0x0000ff);
+    }
+
+    #[test]
+    fn apply_share_gives_back_the_table_share_was_made_from() {
+        let lfos = get_lfos();
+        // Every key gets its own color, so a blob that mixes up any two keys builds another table
+        let mut overrides: HashMap<String, u32> = lfos.keys.iter().enumerate().map(|(i, key)| (key.to_string(), i as u32 * 0x010203)).collect();
+        overrides.insert("all".to_string(), 0x123456);
+        let blob = share::encode(&lfos, 0x123456, &resolve_colors(&lfos, &overrides));
+        let shared = share::decode(&lfos, &blob).unwrap();
+        assert_eq!(build_table(&lfos.keys, &shared, 0xffffff, ChannelOrder::Rgb), build_table(&lfos.keys, &overrides, 0xffffff, ChannelOrder::Rgb));
+    }
+
+    #[test]
+    fn odd_args_blames_the_key_without_a_color() {
+        let lfos = get_lfos();
+        let given: Vec<String> = ["a", "red", "home", "end", "blue"].iter().map(|arg| arg.to_string()).collect();
+        match odd_args(&lfos, None, &given.iter().collect::<Vec<_>>()) {
+            LfosError::OddArgs { key, position: 3, .. } if key == "home" => (),
+            err => panic!("expected 'home' at position 3, got: {}", err),
+        }
+    }
+
+    #[test]
+    fn toggle_with_no_persist_fails() {
+        let lfos = get_lfos();
+        let command = try_parse_cmd(&lfos, &args(&["--no-persist", "toggle", "a"]), &Config::default()).unwrap();
+        assert!(matches!(saved_state(&command), Err(LfosError::PersistDisabled)));
+    }
+
+    #[test]
+    fn keys_with_other_characters_have_an_ascii_alias() {
+        let lfos = get_lfos();
+        for key in lfos.keys.iter().filter(|key| !key.is_ascii()) {
+            assert!(lfos.aliases.iter().any(|(alias, target)| target == key && alias.is_ascii()), "{} has no ASCII alias", key);
+        }
+    }
+
+    #[test]
+    fn access_errors_while_opening_explain_how_to_fix_them() {
+        match open_error(LfosError::Usb(rusb::Error::Access)) {
+            err @ LfosError::AccessDenied => assert!(err.to_string().contains(PERMISSION_HINT), "{}", err),
+            err => panic!("expected access_denied, got: {}", err),
+        }
+    }
+
+    #[test]
+    fn combos_add_and_take_away_groups() {
+        let lfos = get_lfos();
+        let expanded = |name: &str| expand_keys(&lfos, name).unwrap();
+        let mut union = expanded("fkeys");
+        union.extend(expanded("arrows"));
+        let mut difference = expanded("numpad");
+        difference.retain(|key| key != "numpad0");
+        let combos = [("fkeys+arrows", union), ("numpad-numpad0", difference), ("numpad+", vec!["numpad+".to_string()])];
+        for (combo, expected) in combos {
+            assert_eq!(expanded(combo), expected, "{}", combo);
+        }
+        match expand_keys(&lfos, "fkeys+nokey") {
+            Err(LfosError::UnknownKey { key, .. }) => assert_eq!(key, "nokey"),
+            other => panic!("expected unknown_key, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn float_colors_are_clamped_and_kept_whole_in_gradients() {
+        for (text, expected) in [("f:1,0,0", 0xff0000), ("f:0.5,0.5,0.5", 0x808080), ("f:2,-1,0", 0xff0000)] {
+            assert_eq!(parse_color(text).unwrap(), expected, "{}", text);
+        }
+        for text in ["f:1,0", "f:1,x,0"] {
+            assert!(parse_color(text).is_err(), "{}", text);
+        }
+        assert_eq!(split_stops("f:1,0,0,00ff00,f:0,0,1"), ["f:1,0,0", "00ff00", "f:0,0,1"]);
+    }
+
+    #[test]
+    fn since_state_counts_only_the_keys_that_changed() {
+        let lfos = get_lfos();
+        let before = resolve_colors(&lfos, &HashMap::from([("all".to_string(), 0x0000ff)]));
+        let mut after = before.clone();
+        for key in expand_keys(&lfos, "fkeys").unwrap().into_iter().chain(["esc".to_string()]) {
+            after.insert(key, 0xff0000);
+        }
+        after.insert("space".to_string(), 0x0000ff);
+        assert_eq!(changed_keys(&before, &before), 0);
+        assert_eq!(changed_keys(&before, &after), 13);
+        assert_eq!(changed_keys(&BTreeMap::new(), &before), before.len());
+    }
+
+    #[test]
+    fn only_lights_the_listed_keys_in_their_own_colors() {
+        let lfos = get_lfos();
+        let given = HashMap::from([("all".to_string(), 0xff0000), ("home".to_string(), 0x0000ff)]);
+        let lit: Vec<_> = resolve_colors(&lfos, &only_keys(&given, &["home".to_string(), "f1".to_string()]))
+            .into_iter()
+            .filter(|(_, color)| *color != 0)
+            .collect();
+        assert_eq!(lit, [("f1".to_string(), 0xff0000), ("home".to_string(), 0x0000ff)]);
+    }
+
+    #[test]
+    fn layout_covers_every_key_position_once() {
+        let lfos = get_lfos();
+        // The layout a configurator draws has to cover get_key_positions exactly, without keys drawn over each other
+        let layout = layout_keys(&lfos);
+        for (key, position) in &lfos.positions {
+            let entries: Vec<_> = layout.iter().filter(|entry| entry["name"] == *key).collect();
+            assert_eq!(entries.len(), 1, "{} is listed {} times", key, entries.len());
+            assert_eq!((&entries[0]["row"], &entries[0]["col"]), (&position.0.into(), &position.1.into()), "{}", key);
+        }
+        let mut cells = HashMap::new();
+        for entry in layout.iter().filter(|entry| entry["reserved"] == false) {
+            let number = |field: &str| entry[field].as_u64().unwrap_or_default();
+            for row in number("row")..number("row") + number("height") {
+                for col in number("col")..number("col") + number("width") {
+                    if let Some(other) = cells.insert((row, col), &entry["name"]) {
+                        panic!("{} and {} are drawn over each other at ({}, {})", other, entry["name"], row, col);
+                    }
+                }
+            }
+        }
+        for (key, _) in KEY_SIZES {
+            assert!(lfos.positions.contains_key(key), "KEY_SIZES has a size for {}, which has no position", key);
+        }
+    }
+}
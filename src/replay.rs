@@ -0,0 +1,116 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    append_line, build_lfos_table, decode_hex,
+    effect::{saved_colors, send, wait},
+    error::{LfosError, LfosResult, Problem},
+    read_profile, to_hex, Command, Keyboard, LFOS, PACKET_LEN,
+};
+
+struct Packet {
+    // How long to wait after the packet before it, as recorded
+    delay: Duration,
+    data: Vec<u8>,
+}
+
+// A line of a capture is the time it was sent, in seconds since the Unix epoch, and the packet as hex. Times that
+// can't be read only lose the pause before their packet, packets that can't be read stop the whole replay
+fn parse(name: &str, text: &str) -> LfosResult<Vec<Packet>> {
+    let mut packets = Vec::new();
+    let mut problems = Vec::new();
+    let mut last = None;
+    for (index, line) in text.lines().enumerate() {
+        let (time, hex) = match line.trim().split_once(char::is_whitespace) {
+            Some((time, hex)) => (time, hex.trim()),
+            None if line.trim().is_empty() => continue,
+            None => ("", line.trim()),
+        };
+
+        let data = match decode_hex(hex).filter(|data| data.len() == PACKET_LEN) {
+            Some(data) => data,
+            None => {
+                problems.push(Problem {
+                    line: Some(index + 1),
+                    key: None,
+                    message: format!("expected a packet of {} bytes as hex", PACKET_LEN),
+                });
+                continue;
+            }
+        };
+
+        let time = time.parse().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+        if time.is_none() {
+            eprintln!("replay: line {} has no time it was sent, playing it right after the one before", index + 1);
+        }
+        // Clocks can be set back while capturing, which is no reason to wait
+        let delay = match (last, time) {
+            (Some(last), Some(time)) => time.saturating_sub(last),
+            _ => Duration::ZERO,
+        };
+        last = time.or(last);
+        packets.push(Packet { delay, data });
+    }
+
+    if !problems.is_empty() {
+        return Err(LfosError::InvalidProfile {
+            name: name.to_string(),
+            problems,
+        });
+    }
+    Ok(packets)
+}
+
+fn print_packet(command: &Command, packet: &[u8]) {
+    match &command.output_file {
+        Some(path) => {
+            if let Err(err) = append_line(path, &to_hex(packet)) {
+                eprintln!("could not write the packets to '{}': {}", path.display(), err);
+            }
+        }
+        None => println!("{}", to_hex(packet)),
+    }
+}
+
+pub fn run(lfos: &LFOS, command: &Command, path: &str, looped: bool) -> LfosResult<()> {
+    let name = if path == "-" { "<stdin>" } else { path };
+    let packets = parse(name, &read_profile(path)?)?;
+    if packets.is_empty() {
+        eprintln!("replay: {} has no packets", name);
+        return Ok(());
+    }
+
+    let mut keyboard = if command.dry_run { None } else { Some(Keyboard::open(command)?) };
+    let running = Arc::new(AtomicBool::new(true));
+    let handler = running.clone();
+    ctrlc::set_handler(move || handler.store(false, Ordering::SeqCst)).map_err(std::io::Error::other)?;
+
+    // Each time around starts over right away, the capture doesn't say how long to wait after its last packet
+    'replay: loop {
+        for (i, packet) in packets.iter().enumerate() {
+            if i > 0 {
+                wait(&running, packet.delay);
+            }
+            if !running.load(Ordering::SeqCst) {
+                break 'replay;
+            }
+            match &mut keyboard {
+                Some(keyboard) => keyboard.write_packet(command, &packet.data),
+                None => print_packet(command, &packet.data),
+            }
+        }
+        if !looped {
+            return Ok(());
+        }
+    }
+
+    // Stopped halfway, so go back to the saved colors instead of leaving a random frame
+    let overrides = saved_colors(lfos, command)?.into_iter().collect();
+    send(&mut keyboard, command, &build_lfos_table(lfos, &overrides, command));
+    Ok(())
+}
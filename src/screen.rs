@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder},
+    rust_connection::RustConnection,
+};
+
+use crate::{
+    apply_state, build_lfos_table,
+    effect::wait,
+    error::{LfosError, LfosResult},
+    load_state, print_table, resolve_colors, save_state,
+    state::State,
+    Command, Keyboard, LFOS,
+};
+
+// Only every STEP-th pixel of every STEP-th row is read, the average barely changes and it's much faster
+const STEP: usize = 4;
+
+fn x11_error(err: impl std::fmt::Display) -> LfosError {
+    LfosError::Io(io::Error::other(format!("could not capture the screen: {}", err)))
+}
+
+pub struct Screen {
+    connection: RustConnection,
+    root: u32,
+    width: u16,
+    height: u16,
+}
+
+impl Screen {
+    pub fn connect() -> LfosResult<Screen> {
+        let (connection, number) = x11rb::connect(None).map_err(x11_error)?;
+        let screen = &connection.setup().roots[number];
+        let (root, width, height) = (screen.root, screen.width_in_pixels, screen.height_in_pixels);
+        Ok(Screen {
+            connection,
+            root,
+            width,
+            height,
+        })
+    }
+
+    pub fn average(&self) -> LfosResult<u32> {
+        let image = self
+            .connection
+            .get_image(ImageFormat::Z_PIXMAP, self.root, 0, 0, self.width, self.height, !0)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        let setup = self.connection.setup();
+        let bits = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == image.depth)
+            .map_or(0, |format| format.bits_per_pixel);
+        if bits != 32 {
+            return Err(x11_error(format!("{} bits per pixel is not supported", bits)));
+        }
+
+        let stride = self.width as usize * 4;
+        let (mut red, mut green, mut blue, mut count) = (0u64, 0u64, 0u64, 0u64);
+        for row in image.data.chunks_exact(stride).step_by(STEP) {
+            for pixel in row.chunks_exact(4).step_by(STEP) {
+                // Pixels are 0xXXRRGGBB words, in the byte order of the X server
+                let word = match setup.image_byte_order {
+                    ImageOrder::MSB_FIRST => u32::from_be_bytes(pixel.try_into().unwrap()),
+                    _ => u32::from_le_bytes(pixel.try_into().unwrap()),
+                };
+                red += (word >> 16 & 0xff) as u64;
+                green += (word >> 8 & 0xff) as u64;
+                blue += (word & 0xff) as u64;
+                count += 1;
+            }
+        }
+
+        let count = count.max(1);
+        Ok(((red / count) << 16 | (green / count) << 8 | (blue / count)) as u32)
+    }
+}
+
+fn accent(state: &mut State, keys: &[String], color: u32) {
+    for key in keys {
+        state.colors.insert(key.clone(), color);
+    }
+}
+
+pub fn run(lfos: &LFOS, command: &Command, keys: &[String], interval: Option<Duration>) -> LfosResult<()> {
+    let screen = Screen::connect()?;
+    let mut state = load_state(command)?.unwrap_or_default();
+    if state.colors.is_empty() {
+        state.colors = resolve_colors(lfos, &HashMap::new());
+    }
+
+    let interval = match interval {
+        Some(interval) => interval,
+        None => {
+            accent(&mut state, keys, screen.average()?);
+            return apply_state(lfos, command, &state);
+        }
+    };
+
+    let mut keyboard = if command.dry_run { None } else { Some(Keyboard::open(command)?) };
+    let running = Arc::new(AtomicBool::new(true));
+    let handler = running.clone();
+    ctrlc::set_handler(move || handler.store(false, Ordering::SeqCst)).map_err(io::Error::other)?;
+
+    let mut last = None;
+    while running.load(Ordering::SeqCst) {
+        let color = screen.average()?;
+        // Writing the same colors again would only keep the USB bus busy
+        if last != Some(color) {
+            accent(&mut state, keys, color);
+            let overrides = state.colors.clone().into_iter().collect();
+            let table = build_lfos_table(lfos, &overrides, command);
+            match &mut keyboard {
+                Some(keyboard) => {
+                    keyboard.write_table(command, &table);
+                    save_state(command, &state, Some(keyboard));
+                }
+                None => print_table(command, &table),
+            }
+            last = Some(color);
+        }
+        wait(&running, interval);
+    }
+    Ok(())
+}
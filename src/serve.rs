@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs,
+    io::{self, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, OpenOptionsExt},
+    },
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use log::trace;
+
+use crate::{
+    apply_profile_text, build_lfos_table, default_pipe_path,
+    error::{LfosError, LfosResult},
+    load_state, reset_table, resolve_colors, save_state,
+    state::State,
+    Command, Keyboard, LFOS,
+};
+
+fn is_fifo(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+fn open_helper(path: &Path) -> Option<fs::File> {
+    if !is_fifo(path) {
+        return None;
+    }
+
+    // Opening a FIFO for writing without blocking only works while someone is reading it
+    fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .ok()
+}
+
+pub fn hand_off(command: &Command, state: &State) -> LfosResult<bool> {
+    let path = default_pipe_path();
+    let mut helper = match open_helper(&path) {
+        Some(helper) => helper,
+        None => return Ok(false),
+    };
+
+    if !command.unshared.is_empty() {
+        return Err(LfosError::HelperOptions {
+            pipe: path.display().to_string(),
+            options: command.unshared.iter().map(|option| option.to_string()).collect(),
+        });
+    }
+
+    // Save first, so the helper keeps the toggled colors of keys that are still off
+    save_state(command, state, None);
+    let profile: toml::Table = state
+        .colors
+        .iter()
+        .map(|(key, color)| (key.clone(), toml::Value::String(format!("{:06x}", color))))
+        .collect();
+    helper.write_all(profile.to_string().as_bytes())?;
+    trace!("Handed the colors to the helper on {}", path.display());
+
+    Ok(true)
+}
+
+fn create_fifo(path: &Path) -> LfosResult<bool> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => return Ok(false),
+        Ok(_) => {
+            return Err(LfosError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' already exists and is not a named pipe", path.display()),
+            )))
+        }
+        Err(_) => (),
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(true)
+}
+
+fn listen(lfos: &LFOS, command: &Command, keyboard: &Mutex<Keyboard>, path: &Path) -> LfosResult<()> {
+    let name = path.display().to_string();
+    loop {
+        // Opening the pipe waits for a writer, and reading stops when it closes its end
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            // The pipe was removed or taken away from us, so nothing will come through it again
+            Err(err) if matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied) => return Err(err.into()),
+            // Like a bad profile, a bad write is only the writer's problem
+            Err(source) => {
+                eprintln!("{}", LfosError::ProfileRead { path: name.clone(), source });
+                continue;
+            }
+        };
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(_) => {
+                let message = "the profile isn't UTF-8 text".to_string();
+                eprintln!("{}", LfosError::ProfileSyntax { name: name.clone(), message });
+                continue;
+            }
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let mut overrides = HashMap::new();
+        match apply_profile_text(lfos, &mut overrides, &name, &text, command.blend, command.interpolation) {
+            Ok(_) => {
+                let table = build_lfos_table(lfos, &overrides, command);
+                let mut keyboard = keyboard.lock().unwrap();
+                keyboard.write_table(command, &table);
+                let colors = resolve_colors(lfos, &overrides);
+                let mut toggled = load_state(command).ok().flatten().unwrap_or_default().toggled;
+                toggled.retain(|key, _| colors.get(key) == Some(&0));
+                let state = State {
+                    colors,
+                    toggled,
+                    ..State::default()
+                };
+                save_state(command, &state, Some(&keyboard));
+            }
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+}
+
+pub fn serve(lfos: &LFOS, command: &Command, path: &Path) -> LfosResult<()> {
+    if open_helper(path).is_some() {
+        return Err(LfosError::Io(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another instance is already serving {}", path.display()),
+        )));
+    }
+
+    let keyboard = Arc::new(Mutex::new(Keyboard::open(command)?));
+    let created = create_fifo(path)?;
+
+    let cleanup = path.to_path_buf();
+    let reset = reset_table(lfos, command);
+    let reset_keyboard = Arc::downgrade(&keyboard);
+    let reset_command = command.clone();
+    ctrlc::set_handler(move || {
+        if created {
+            fs::remove_file(&cleanup).ok();
+        }
+        // Opening the keyboard again would fail while this process has it claimed
+        if let Some(keyboard) = reset_keyboard.upgrade() {
+            let mut keyboard = keyboard.lock().unwrap();
+            if let Some(table) = &reset {
+                keyboard.write_table(&reset_command, table);
+            }
+            // Exiting skips Drop, which would otherwise give the interface back to the kernel driver
+            keyboard.release();
+        }
+        std::process::exit(0);
+    })
+    .map_err(io::Error::other)?;
+
+    eprintln!("Waiting for profiles on {}", path.display());
+    let result = listen(lfos, command, &keyboard, path);
+    // Let go of the keyboard, so the reset on the way out can open it
+    drop(keyboard);
+    if created {
+        fs::remove_file(path).ok();
+    }
+
+    result
+}
@@ -0,0 +1,82 @@
+use std::collections::{BTreeMap, HashMap};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::{
+    error::{LfosError, LfosResult},
+    LFOS,
+};
+
+// Bumped whenever the layout below changes, so older builds turn down blobs they would misread
+const SHARE_VERSION: u8 = 1;
+
+// A blob is the version, the color for "all", then one color per key in the order of get_keys
+// (leaving out the "????" slots), three bytes each, and a CRC-32 of everything before it
+fn layout_len(lfos: &LFOS) -> usize {
+    1 + 3 + 3 * real_keys(lfos).count() + 4
+}
+
+fn real_keys<'a>(lfos: &'a LFOS) -> impl Iterator<Item = &'static str> + 'a {
+    lfos.keys.iter().copied().filter(|key| *key != "????")
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn invalid(reason: impl ToString) -> LfosError {
+    LfosError::InvalidShare {
+        reason: reason.to_string(),
+    }
+}
+
+pub fn encode(lfos: &LFOS, all: u32, colors: &BTreeMap<String, u32>) -> String {
+    let mut data = vec![SHARE_VERSION];
+    data.extend_from_slice(&all.to_be_bytes()[1..]);
+    for key in real_keys(lfos) {
+        let color = colors.get(key).copied().unwrap_or(all);
+        data.extend_from_slice(&color.to_be_bytes()[1..]);
+    }
+    let crc = crc32(&data);
+    data.extend_from_slice(&crc.to_be_bytes());
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+// Gives back "all" and every key, so the table built from them is the same one the blob was made from
+pub fn decode(lfos: &LFOS, blob: &str) -> LfosResult<HashMap<String, u32>> {
+    let data = URL_SAFE_NO_PAD
+        .decode(blob.trim())
+        .map_err(|_| invalid("it isn't a blob made by share, or it wasn't copied whole"))?;
+    match data.first() {
+        Some(&SHARE_VERSION) => (),
+        Some(version) => {
+            return Err(invalid(format!(
+                "it was made by another version of the program (format {}, this one reads format {})",
+                version, SHARE_VERSION
+            )))
+        }
+        None => return Err(invalid("it's empty")),
+    }
+    if data.len() != layout_len(lfos) {
+        return Err(invalid("it's damaged or wasn't copied whole"));
+    }
+    let (body, crc) = data.split_at(data.len() - 4);
+    if crc32(body) != u32::from_be_bytes(crc.try_into().unwrap()) {
+        return Err(invalid("it's damaged or wasn't copied whole"));
+    }
+
+    let mut colors = body[1..].chunks_exact(3).map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]));
+    let mut overrides = HashMap::new();
+    overrides.insert("all".to_string(), colors.next().unwrap_or_default());
+    for (key, color) in real_keys(lfos).zip(colors) {
+        overrides.insert(key.to_string(), color);
+    }
+    Ok(overrides)
+}
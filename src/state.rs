@@ -0,0 +1,141 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{LfosError, LfosResult};
+
+#[derive(Clone, Default)]
+pub struct State {
+    pub colors: BTreeMap<String, u32>,
+    pub toggled: BTreeMap<String, u32>,
+    // The alternate setting of the USB interface that took the colors, only kept per keyboard
+    pub alt_setting: Option<u8>,
+}
+
+fn state_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+}
+
+pub fn state_path() -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("state.toml"))
+}
+
+pub fn device_state_path(id: &str) -> Option<PathBuf> {
+    state_dir().map(|dir| dir.join("devices").join(format!("{}.toml", id)))
+}
+
+pub fn device_ids() -> Vec<String> {
+    let mut ids: Vec<String> = state_dir()
+        .and_then(|dir| fs::read_dir(dir.join("devices")).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().to_string())
+        })
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn state_error(path: &Path, reason: impl ToString) -> LfosError {
+    LfosError::State {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn read_colors(table: &toml::Table, name: &str) -> Result<BTreeMap<String, u32>, String> {
+    let mut colors = BTreeMap::new();
+    let section = match table.get(name) {
+        Some(toml::Value::Table(section)) => section,
+        Some(_) => return Err(format!("'{}' must be a table", name)),
+        None => return Ok(colors),
+    };
+
+    for (key, value) in section {
+        let color = value
+            .as_str()
+            .and_then(|color| u32::from_str_radix(color, 16).ok())
+            .ok_or_else(|| format!("invalid color for '{}' in '{}'", key, name))?;
+        colors.insert(key.clone(), color);
+    }
+
+    Ok(colors)
+}
+
+fn write_colors(colors: &BTreeMap<String, u32>) -> toml::Table {
+    colors
+        .iter()
+        .map(|(key, color)| (key.clone(), toml::Value::String(format!("{:06x}", color))))
+        .collect()
+}
+
+impl State {
+    pub fn load() -> LfosResult<Option<State>> {
+        State::load_from(state_path())
+    }
+
+    pub fn load_device(id: &str) -> LfosResult<Option<State>> {
+        State::load_from(device_state_path(id))
+    }
+
+    fn load_from(path: Option<PathBuf>) -> LfosResult<Option<State>> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(state_error(&path, err)),
+        };
+        let table: toml::Table = text.parse().map_err(|err| state_error(&path, err))?;
+
+        Ok(Some(State {
+            colors: read_colors(&table, "colors").map_err(|err| state_error(&path, err))?,
+            toggled: read_colors(&table, "toggled").map_err(|err| state_error(&path, err))?,
+            alt_setting: table
+                .get("usb")
+                .and_then(|usb| usb.get("alt_setting"))
+                .and_then(|setting| setting.as_integer())
+                .and_then(|setting| u8::try_from(setting).ok()),
+        }))
+    }
+
+    pub fn save(&self) -> LfosResult<()> {
+        let shared = State {
+            alt_setting: None,
+            ..self.clone()
+        };
+        shared.save_to(state_path())
+    }
+
+    pub fn save_device(&self, id: &str) -> LfosResult<()> {
+        self.save_to(device_state_path(id))
+    }
+
+    fn save_to(&self, path: Option<PathBuf>) -> LfosResult<()> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| state_error(&path, err))?;
+        }
+
+        let mut table = toml::Table::new();
+        table.insert("colors".to_string(), toml::Value::Table(write_colors(&self.colors)));
+        table.insert("toggled".to_string(), toml::Value::Table(write_colors(&self.toggled)));
+        if let Some(setting) = self.alt_setting {
+            let usb = toml::Table::from_iter([("alt_setting".to_string(), toml::Value::Integer(setting as i64))]);
+            table.insert("usb".to_string(), toml::Value::Table(usb));
+        }
+        fs::write(&path, table.to_string()).map_err(|err| state_error(&path, err))
+    }
+}
@@ -0,0 +1,61 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{color::contrast_text_color, LFOS};
+
+const BACKGROUND: &str = "#202020";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn render(lfos: &LFOS, colors: &BTreeMap<String, u32>, key_size: u32, gap: u32) -> String {
+    let mut keys: Vec<_> = lfos.positions.iter().collect();
+    keys.sort_by_key(|(_, position)| **position);
+
+    let cell = key_size + gap;
+    let rows = keys.iter().map(|(_, (row, _))| *row as u32 + 1).max().unwrap_or(0);
+    let cols = keys.iter().map(|(_, (_, col))| *col as u32 + 1).max().unwrap_or(0);
+    let (width, height) = (cols * cell + gap, rows * cell + gap);
+
+    let mut svg = String::new();
+    // Writing to a String can't fail
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        width, height
+    )
+    .ok();
+    writeln!(svg, r#"<rect width="100%" height="100%" fill="{}"/>"#, BACKGROUND).ok();
+    for (key, (row, col)) in keys {
+        let x = gap + *col as u32 * cell;
+        let y = gap + *row as u32 * cell;
+        let color = colors.get(*key).copied().unwrap_or(0xffffff);
+        writeln!(
+            svg,
+            r##"<rect x="{}" y="{}" width="{2}" height="{2}" rx="{3}" fill="#{4:06x}"/>"##,
+            x,
+            y,
+            key_size,
+            key_size / 8,
+            color & 0xffffff
+        )
+        .ok();
+        writeln!(
+            svg,
+            r##"<text x="{}" y="{}" font-family="sans-serif" font-size="{}" fill="#{:06x}" text-anchor="middle" dominant-baseline="central">{}</text>"##,
+            x + key_size / 2,
+            y + key_size / 2,
+            (key_size / 5).max(1),
+            contrast_text_color(color),
+            escape(key)
+        )
+        .ok();
+    }
+    svg.push_str("</svg>\n");
+
+    svg
+}
@@ -0,0 +1,57 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A ready made scheme, applied from top to bottom like a profile
+pub struct Theme {
+    pub name: &'static str,
+    pub colors: &'static [(&'static str, &'static str)],
+}
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "ocean",
+        colors: &[("all", "003060"), ("letters", "0080c0"), ("fkeys", "00c0c0"), ("numpad", "004080"), ("arrows", "00ffff")],
+    },
+    Theme {
+        name: "sunset",
+        colors: &[("all", "ff4000"), ("letters", "ff8000"), ("fkeys", "ff0040"), ("numpad", "c00060"), ("arrows", "ffc000")],
+    },
+    Theme {
+        name: "forest",
+        colors: &[("all", "104010"), ("letters", "30a030"), ("fkeys", "80c000"), ("numpad", "206020"), ("arrows", "c0ff40")],
+    },
+    Theme {
+        name: "ember",
+        colors: &[("all", "400000"), ("letters", "ff2000"), ("fkeys", "ff8000"), ("numpad", "800000"), ("arrows", "ffc000")],
+    },
+    Theme {
+        name: "arctic",
+        colors: &[("all", "c0e0ff"), ("letters", "ffffff"), ("fkeys", "80c0ff"), ("numpad", "a0d0ff"), ("arrows", "4080ff")],
+    },
+    Theme {
+        name: "neon",
+        colors: &[("all", "200040"), ("letters", "ff00ff"), ("fkeys", "00ffff"), ("numpad", "8000ff"), ("arrows", "00ff80")],
+    },
+    Theme {
+        name: "candy",
+        colors: &[("all", "ff80c0"), ("letters", "ffc0e0"), ("fkeys", "80ffc0"), ("numpad", "c080ff"), ("arrows", "ffff80")],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Theme> {
+    THEMES.iter().find(|theme| theme.name == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    THEMES.iter().map(|theme| theme.name).collect()
+}
+
+// Whole days since 1970-01-01, so the day changes at midnight UTC
+pub fn today() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86400
+}
+
+// Goes through the themes in order, one per day, so the same day always gets the same theme and the next day
+// always gets another one
+pub fn daily(day: u64, seed: u64) -> &'static Theme {
+    &THEMES[(day.wrapping_add(seed) % THEMES.len() as u64) as usize]
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{self, Color, Stylize},
+    terminal,
+};
+
+use crate::{
+    build_lfos_table, color::contrast_text_color, error::LfosResult, load_state, parse_color, resolve_colors, save_state, state::State,
+    Command, Keyboard, LFOS,
+};
+
+const CELL_WIDTH: u16 = 5;
+const STATUS_ROW: u16 = 12;
+
+enum Step {
+    Continue,
+    Apply,
+    Quit,
+}
+
+struct Editor<'a> {
+    lfos: &'a LFOS,
+    keys: Vec<(&'static str, (u8, u8))>,
+    colors: BTreeMap<String, u32>,
+    selected: usize,
+    input: Option<String>,
+    message: String,
+}
+
+fn short_label(key: &str) -> String {
+    let label = match key {
+        "leftarrow" => "←",
+        "rightarrow" => "→",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "numpadenter" => "ent",
+        _ => key.strip_prefix("numpad").unwrap_or(key),
+    };
+    label.chars().take(CELL_WIDTH as usize - 1).collect()
+}
+
+fn to_rgb(color: u32) -> Color {
+    Color::Rgb {
+        r: (color >> 16) as u8,
+        g: (color >> 8) as u8,
+        b: color as u8,
+    }
+}
+
+impl<'a> Editor<'a> {
+    fn new(lfos: &'a LFOS, colors: BTreeMap<String, u32>) -> Editor<'a> {
+        let mut keys: Vec<_> = lfos.positions.iter().map(|(key, position)| (*key, *position)).collect();
+        keys.sort_by_key(|(_, position)| *position);
+
+        Editor {
+            lfos,
+            keys,
+            colors,
+            selected: 0,
+            input: None,
+            message: String::new(),
+        }
+    }
+
+    fn selected_key(&self) -> &'static str {
+        self.keys[self.selected].0
+    }
+
+    fn color(&self, key: &str) -> u32 {
+        *self.colors.get(key).unwrap_or(&0xffffff)
+    }
+
+    fn move_selection(&mut self, rows: i16, cols: i16) {
+        let (row, col) = self.keys[self.selected].1;
+        let (row, col) = (row as i16, col as i16);
+        let candidate = self
+            .keys
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, (r, c)))| {
+                let (r, c) = (*r as i16, *c as i16);
+                if rows != 0 {
+                    (r - row).signum() == rows
+                } else {
+                    r == row && (c - col).signum() == cols
+                }
+            })
+            .min_by_key(|(_, (_, (r, c)))| ((*r as i16 - row).abs(), (*c as i16 - col).abs()))
+            .map(|(i, _)| i);
+
+        if let Some(i) = candidate {
+            self.selected = i;
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Step {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Step::Quit;
+        }
+
+        if let Some(text) = self.input.as_mut() {
+            match key.code {
+                KeyCode::Char(c) if c.is_ascii_hexdigit() && text.len() < 6 => text.push(c),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Enter => {
+                    let text = self.input.take().unwrap_or_default();
+                    match parse_color(&text) {
+                        Ok(color) => {
+                            self.colors.insert(self.selected_key().to_string(), color);
+                            return Step::Apply;
+                        }
+                        Err(err) => self.message = err.to_string(),
+                    }
+                }
+                _ => (),
+            }
+            return Step::Continue;
+        }
+
+        match key.code {
+            KeyCode::Left => self.move_selection(0, -1),
+            KeyCode::Right => self.move_selection(0, 1),
+            KeyCode::Up => self.move_selection(-1, 0),
+            KeyCode::Down => self.move_selection(1, 0),
+            KeyCode::Enter | KeyCode::Char('c') => {
+                self.input = Some(String::new());
+                self.message.clear();
+            }
+            KeyCode::Esc | KeyCode::Char('q') => return Step::Quit,
+            _ => (),
+        }
+
+        Step::Continue
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+
+        for (i, (key, (row, col))) in self.keys.iter().enumerate() {
+            let color = self.color(key);
+            let label = format!("{:^1$}", short_label(key), CELL_WIDTH as usize - 1);
+            // The same black or white the SVG preview picks, so both previews label a key alike
+            let text = if contrast_text_color(color) == 0x000000 { Color::Black } else { Color::White };
+            let mut styled = label.on(to_rgb(color)).with(text);
+            if i == self.selected {
+                styled = styled.bold().underlined();
+            }
+            queue!(
+                out,
+                cursor::MoveTo(*col as u16 * CELL_WIDTH, *row as u16 * 2),
+                style::PrintStyledContent(styled)
+            )?;
+        }
+
+        let key = self.selected_key();
+        let help = match &self.input {
+            Some(text) => format!("new color for {}: {}_ (enter: apply, esc: cancel)", key, text),
+            None => "arrows: move, enter: set color, q: quit".to_string(),
+        };
+        queue!(
+            out,
+            cursor::MoveTo(0, STATUS_ROW),
+            style::Print(format!("{}: {:06x}", key, self.color(key))),
+            cursor::MoveTo(0, STATUS_ROW + 1),
+            style::Print(help),
+            cursor::MoveTo(0, STATUS_ROW + 2),
+            style::Print(&self.message)
+        )?;
+
+        out.flush()
+    }
+
+    fn edit(&mut self, out: &mut impl Write, keyboard: &mut Keyboard, command: &Command) -> LfosResult<bool> {
+        let mut applied = false;
+        loop {
+            self.draw(out)?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+
+            match self.handle_key(key) {
+                Step::Continue => (),
+                Step::Apply => {
+                    let overrides: HashMap<String, u32> = self.colors.clone().into_iter().collect();
+                    keyboard.write_table(command, &build_lfos_table(self.lfos, &overrides, command));
+                    applied = true;
+                }
+                Step::Quit => return Ok(applied),
+            }
+        }
+    }
+}
+
+pub fn run(lfos: &LFOS, command: &Command) -> LfosResult<()> {
+    let mut keyboard = Keyboard::open(command)?;
+    let colors = match load_state(command)? {
+        Some(state) if !state.colors.is_empty() => state.colors,
+        _ => resolve_colors(lfos, &HashMap::new()),
+    };
+    let mut editor = Editor::new(lfos, colors);
+
+    let mut out = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let result = editor.edit(&mut out, &mut keyboard, command);
+    execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+
+    if result? {
+        let state = State {
+            colors: editor.colors,
+            ..State::default()
+        };
+        save_state(command, &state, Some(&keyboard));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn home(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}-{}", env!("CARGO_PKG_NAME"), name, std::process::id()))
+}
+
+// Runs the binary without the config file, saved colors or default profile of whoever runs the tests
+fn run(args: &[&str]) -> Output {
+    run_in(&home("cli"), args)
+}
+
+fn run_in(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lights-for-omen-sequencer"))
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_DATA_HOME", home.join("data"))
+        .env_remove("LFOS_DEFAULT_PROFILE")
+        .output()
+        .unwrap()
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[test]
+fn help_and_version_go_to_stdout() {
+    for (arg, expected) in [("--help", "Usage:"), ("-h", "Usage:"), ("--version", env!("CARGO_PKG_VERSION")), ("-v", env!("CARGO_PKG_VERSION"))] {
+        let output = run(&[arg]);
+        assert!(output.status.success(), "{}", arg);
+        assert!(text(&output.stdout).contains(expected), "{} printed: {}", arg, text(&output.stdout));
+        assert_eq!(text(&output.stderr), "", "{}", arg);
+    }
+}
+
+#[test]
+fn errors_go_to_stderr() {
+    let output = run(&["--no-such-option"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(text(&output.stdout), "");
+    assert!(text(&output.stderr).contains("see --help"), "{}", text(&output.stderr));
+}
+
+#[test]
+fn dry_run_prints_packets_to_stdout_and_warnings_to_stderr() {
+    let output = run(&["--dry-run", "--no-persist", "--continue-on-error", "home", "red", "end"]);
+    assert!(output.status.success());
+    let stdout = text(&output.stdout);
+    assert!(!stdout.is_empty() && stdout.lines().all(|line| line.starts_with("packet ")), "{}", stdout);
+    assert_eq!(text(&output.stderr), "warning: ignoring 'end', it was not given a color\n");
+}
+
+#[test]
+fn a_broken_config_leaves_help_selftest_and_doctor_working() {
+    let home = home("broken-config");
+    let dir = home.join("config").join(env!("CARGO_PKG_NAME"));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("config.toml"), "brightness = \"lots\"\n").unwrap();
+
+    for args in [&["--help"][..], &["--version"], &["selftest"]] {
+        let output = run_in(&home, args);
+        assert!(output.status.success(), "{:?}: {}", args, text(&output.stderr));
+    }
+    let doctor = text(&run_in(&home, &["doctor"]).stdout);
+    assert!(doctor.starts_with("[FAIL] config: "), "{}", doctor);
+    assert_eq!(run_in(&home, &["--dry-run", "all", "red"]).status.code(), Some(1), "every other command still fails");
+
+    std::fs::remove_dir_all(&home).ok();
+}
+
+// Every golden table next to its profile still comes out the same, so a change to the packets can't slip through
+#[test]
+fn goldens_still_match() {
+    let goldens = Path::new(env!("CARGO_MANIFEST_DIR")).join("goldens");
+    let mut checked = 0;
+    for entry in std::fs::read_dir(&goldens).unwrap() {
+        let golden = entry.unwrap().path();
+        if golden.extension().is_none_or(|extension| extension != "hex") {
+            continue;
+        }
+        let profile = golden.with_extension("toml");
+        let output = run(&["verify-golden", golden.to_str().unwrap(), "--profile", profile.to_str().unwrap()]);
+        assert!(output.status.success(), "{}: {}{}", golden.display(), text(&output.stdout), text(&output.stderr));
+        checked += 1;
+    }
+    assert!(checked > 0, "no goldens in {}", goldens.display());
+}